@@ -25,6 +25,8 @@ pub struct App {
     pub data: Data,
     pub lambda_view_state: LambdasViewState,
     pub install_view_state: InstallViewState,
+    /// Backend URL passed to the installed extensions, they use the built-in one if it's not set
+    pub api_url: Option<String>,
 }
 
 impl Default for App {
@@ -36,13 +38,17 @@ impl Default for App {
             data: Data::default(),
             lambda_view_state: LambdasViewState::default(),
             install_view_state: InstallViewState::default(),
+            api_url: None,
         }
     }
 }
 
 impl App {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(api_url: Option<String>) -> Self {
+        Self {
+            api_url,
+            ..Self::default()
+        }
     }
 
     pub async fn run(&mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
@@ -119,11 +125,17 @@ impl App {
                             let aws_config = aws_config.clone();
                             let sender = self.events.sender_cloned();
                             let secret_arn = secret_arn.clone();
+                            let api_url = self.api_url.clone();
 
                             tokio::spawn(async move {
                                 // TODO Handle errors
-                                let result =
-                                    install_extension(&aws_config, lambda, &secret_arn).await;
+                                let result = install_extension(
+                                    &aws_config,
+                                    lambda,
+                                    &secret_arn,
+                                    api_url.as_deref(),
+                                )
+                                .await;
 
                                 if let Err(e) = result {
                                     println!("Error installing extension: {e:?}");
@@ -174,27 +186,17 @@ impl App {
                 }
             }
 
-            KeyCode::Char('j') | KeyCode::Down => {
-                if self.current_view == ui::View::Lambdas && !self.is_loading() {
-                    self.lambda_view_state.lambda_list.select_next()
-                }
+            KeyCode::Char('j') | KeyCode::Down if self.is_lambdas_view_ready() => {
+                self.lambda_view_state.lambda_list.select_next()
             }
-            KeyCode::Char('k') | KeyCode::Up => {
-                if self.current_view == ui::View::Lambdas && !self.is_loading() {
-                    self.lambda_view_state.lambda_list.select_previous()
-                }
+            KeyCode::Char('k') | KeyCode::Up if self.is_lambdas_view_ready() => {
+                self.lambda_view_state.lambda_list.select_previous()
             }
-            KeyCode::Char(' ') => {
-                if self.current_view == ui::View::Lambdas && !self.is_loading() {
-                    let current_selection = self.lambda_view_state.lambda_list.selected();
-                    self.toggle_lambda(current_selection)
-                }
-            }
-            KeyCode::Char('a') => {
-                if self.current_view == ui::View::Lambdas && !self.is_loading() {
-                    self.toggle_all_lambdas()
-                }
+            KeyCode::Char(' ') if self.is_lambdas_view_ready() => {
+                let current_selection = self.lambda_view_state.lambda_list.selected();
+                self.toggle_lambda(current_selection)
             }
+            KeyCode::Char('a') if self.is_lambdas_view_ready() => self.toggle_all_lambdas(),
             _ => {}
         }
         Ok(())
@@ -204,6 +206,10 @@ impl App {
         matches!(&self.data.lambdas, LoadState::Loading)
     }
 
+    fn is_lambdas_view_ready(&self) -> bool {
+        self.current_view == ui::View::Lambdas && !self.is_loading()
+    }
+
     fn toggle_all_lambdas(&mut self) {
         if let LoadState::Loaded(lambdas) = &mut self.data.lambdas {
             self.lambda_view_state.is_selected_all = !self.lambda_view_state.is_selected_all;
@@ -215,11 +221,11 @@ impl App {
     }
 
     fn toggle_lambda(&mut self, idx: Option<usize>) {
-        if let LoadState::Loaded(lambdas) = &mut self.data.lambdas {
-            if let Some(idx) = idx {
-                let lambda = &mut lambdas[idx];
-                lambda.is_selected = !lambda.is_selected;
-            }
+        if let LoadState::Loaded(lambdas) = &mut self.data.lambdas
+            && let Some(idx) = idx
+        {
+            let lambda = &mut lambdas[idx];
+            lambda.is_selected = !lambda.is_selected;
         }
     }
 
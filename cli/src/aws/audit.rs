@@ -0,0 +1,113 @@
+use aws_config::SdkConfig;
+use aws_sdk_ssm::Client as SsmClient;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+
+/// Memory change recorded by the extension in the SSM audit sink
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub timestamp: String,
+    #[serde(rename = "oldMemorySizeMB")]
+    pub old_memory_size_mb: i32,
+    #[serde(rename = "newMemorySizeMB")]
+    pub new_memory_size_mb: i32,
+    pub strategy: String,
+    pub source: String,
+    pub reason: Option<String>,
+    pub environment_id: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Printed when no records are found, by default the extension only logs the changes
+pub const AUDIT_SINK_HINT: &str =
+    "The extension writes the audit records with OPTIMEIST_AUDIT_SINK=SSM set on the function";
+
+/// SSM keeps this many versions of a parameter, the oldest one is deleted by the next write
+pub const PARAMETER_HISTORY_LIMIT: usize = 100;
+
+/// Default audit parameter of the function, matches the extension default
+pub fn default_parameter_name(function_name: &str) -> String {
+    format!("/optimeist/{function_name}/audit")
+}
+
+/// Reads the audit records from the parameter history, oldest first
+pub async fn get_history(
+    config: &SdkConfig,
+    parameter_name: &str,
+) -> color_eyre::Result<Vec<AuditRecord>> {
+    let client = SsmClient::new(config);
+    let mut pages = client
+        .get_parameter_history()
+        .name(parameter_name)
+        .into_paginator()
+        .send();
+
+    let mut records = Vec::new();
+
+    while let Some(page) = pages.next().await {
+        let page = match page {
+            Ok(page) => page,
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_parameter_not_found()) =>
+            {
+                return Err(eyre!(
+                    "Parameter {parameter_name} not found. {AUDIT_SINK_HINT}"
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for parameter in page.parameters.unwrap_or_default() {
+            // Values that are not audit records are skipped
+            if let Some(record) = parameter
+                .value
+                .and_then(|value| serde_json::from_str(&value).ok())
+            {
+                records.push(record);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+/// Prints the audit records as a table
+pub fn print_history(records: &[AuditRecord]) {
+    if records.is_empty() {
+        println!("No memory changes recorded. {AUDIT_SINK_HINT}");
+        return;
+    }
+
+    println!(
+        "{:<32} {:<15} {:>8} {:>8} {:<8} DETAILS",
+        "TIMESTAMP", "SOURCE", "FROM MB", "TO MB", "STATUS"
+    );
+
+    for record in records {
+        let status = if record.success { "OK" } else { "FAILED" };
+        let details = record
+            .error
+            .as_deref()
+            .or(record.reason.as_deref())
+            .unwrap_or_default();
+
+        println!(
+            "{:<32} {:<15} {:>8} {:>8} {:<8} {} (strategy {}, environment {})",
+            record.timestamp,
+            record.source,
+            record.old_memory_size_mb,
+            record.new_memory_size_mb,
+            status,
+            details,
+            record.strategy,
+            record.environment_id
+        );
+    }
+
+    println!(
+        "\nSSM keeps the last {PARAMETER_HISTORY_LIMIT} versions of the parameter, older changes are not listed"
+    );
+}
@@ -9,6 +9,7 @@ pub async fn install_extension(
     aws_config: &SdkConfig,
     lambda: Lambda,
     secret_arn: &str,
+    api_url: Option<&str>,
 ) -> color_eyre::Result<()> {
     use aws_sdk_lambda::Client as LambdaClient;
     let client = LambdaClient::new(aws_config);
@@ -45,6 +46,10 @@ pub async fn install_extension(
         secret_arn.to_string(),
     );
 
+    if let Some(api_url) = api_url {
+        variables.insert("OPTIMEIST_API_URL".to_string(), api_url.to_string());
+    }
+
     iam_client
         .put_role_policy()
         .role_name(role_name)
@@ -1,3 +1,4 @@
+pub mod audit;
 pub mod install;
 pub mod lambda;
 pub mod policy;
@@ -1,28 +1,143 @@
 use crate::models::Lambda;
 use serde_json::json;
 
+/// Generates the policy of the extension.
+/// The optional features get their permissions if they are configured in the function
+/// environment, so the extension must be installed again after enabling one.
 pub fn generate_policy_for_lambda(lambda: &Lambda, secret_arn: &str) -> String {
+    let mut statements = vec![
+        json!({
+            "Effect": "Allow",
+            "Action": [
+                "lambda:GetFunction",
+                "lambda:UpdateFunctionConfiguration"
+            ],
+            "Resource": lambda.arn
+        }),
+        json!({
+            "Effect": "Allow",
+            "Action": [
+                "secretsmanager:DescribeSecret",
+                "secretsmanager:GetSecretValue"
+            ],
+            "Resource": secret_arn
+        }),
+    ];
+
+    if let Some(table_name) = lambda.variables.get("OPTIMEIST_LEASE_TABLE_NAME") {
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": [
+                "dynamodb:GetItem",
+                "dynamodb:PutItem",
+                "dynamodb:UpdateItem",
+                "dynamodb:DeleteItem"
+            ],
+            "Resource": resource_arn(lambda, "dynamodb", &format!("table/{table_name}"))
+        }));
+    }
+
+    if lambda.variables.contains_key("OPTIMEIST_PUBLISH_ALIAS") {
+        let mut actions = vec!["lambda:PublishVersion", "lambda:UpdateAlias"];
+
+        // The canary rollout reads the memory size of the version the alias points to
+        if lambda.variables.contains_key("OPTIMEIST_CANARY_WEIGHT") {
+            actions.extend(["lambda:GetAlias", "lambda:GetFunction"]);
+        }
+
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": actions,
+            "Resource": [lambda.arn.clone(), format!("{}:*", lambda.arn)]
+        }));
+    }
+
+    if has_variable(lambda, "OPTIMEIST_AUDIT_SINK", "SSM") {
+        let parameter_name = parameter_name(lambda, "OPTIMEIST_AUDIT_PARAMETER_NAME", "audit");
+
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": ["ssm:PutParameter"],
+            "Resource": parameter_arn(lambda, &parameter_name)
+        }));
+    }
+
+    // The local recommender keeps the stats in the lease table if there is one
+    if has_variable(lambda, "OPTIMEIST_DECISION_MODE", "LOCAL")
+        && !lambda.variables.contains_key("OPTIMEIST_LEASE_TABLE_NAME")
+    {
+        let parameter_name = parameter_name(lambda, "OPTIMEIST_STATS_PARAMETER_NAME", "stats");
+
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": ["ssm:GetParameter", "ssm:PutParameter"],
+            "Resource": parameter_arn(lambda, &parameter_name)
+        }));
+    }
+
+    // The change limits are checked against the changes shared by the execution environments
+    if is_positive(lambda, "OPTIMEIST_UPDATE_COOLDOWN_SECONDS")
+        || is_positive(lambda, "OPTIMEIST_MAX_CHANGES_PER_DAY")
+    {
+        let parameter_name = parameter_name(lambda, "OPTIMEIST_CHANGES_PARAMETER_NAME", "changes");
+
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": ["ssm:GetParameter", "ssm:PutParameter"],
+            "Resource": parameter_arn(lambda, &parameter_name)
+        }));
+    }
+
     let policy = json!({
         "Version": "2012-10-17",
-        "Statement": [
-            {
-                "Effect": "Allow",
-                "Action": [
-                    "lambda:GetFunction",
-                    "lambda:UpdateFunctionConfiguration"
-                ],
-                "Resource": lambda.arn
-            },
-            {
-                "Effect": "Allow",
-                "Action": [
-                    "secretsmanager:DescribeSecret",
-                    "secretsmanager:GetSecretValue"
-                ],
-                "Resource": secret_arn
-            }
-        ]
+        "Statement": statements
     });
 
     serde_json::to_string_pretty(&policy).unwrap_or_default()
 }
+
+/// Returns `true` if the variable is set to the value, the case is ignored like in the extension
+fn has_variable(lambda: &Lambda, name: &str, value: &str) -> bool {
+    lambda
+        .variables
+        .get(name)
+        .is_some_and(|variable| variable.eq_ignore_ascii_case(value))
+}
+
+/// Returns `true` if the variable is set to a number above zero, which enables the limit
+fn is_positive(lambda: &Lambda, name: &str) -> bool {
+    lambda
+        .variables
+        .get(name)
+        .and_then(|variable| variable.trim().parse::<u64>().ok())
+        .is_some_and(|value| value > 0)
+}
+
+/// Returns the SSM parameter set in the variable or the `/optimeist/<function>/<suffix>` default
+fn parameter_name(lambda: &Lambda, variable: &str, suffix: &str) -> String {
+    lambda
+        .variables
+        .get(variable)
+        .cloned()
+        .unwrap_or_else(|| format!("/optimeist/{}/{suffix}", lambda.name))
+}
+
+fn parameter_arn(lambda: &Lambda, parameter_name: &str) -> String {
+    resource_arn(
+        lambda,
+        "ssm",
+        &format!("parameter/{}", parameter_name.trim_start_matches('/')),
+    )
+}
+
+/// Builds the ARN of a resource in the partition, region and account of the function
+fn resource_arn(lambda: &Lambda, service: &str, resource: &str) -> String {
+    // arn:partition:lambda:region:account:function:name
+    let parts: Vec<&str> = lambda.arn.split(':').collect();
+    let (partition, region, account) = match parts.as_slice() {
+        [_, partition, _, region, account, ..] => (*partition, *region, *account),
+        _ => ("aws", "*", "*"),
+    };
+
+    format!("arn:{partition}:{service}:{region}:{account}:{resource}")
+}
@@ -5,20 +5,63 @@ mod models;
 pub mod ui;
 
 use crate::app::App;
-use clap::Parser;
+use aws_config::BehaviorVersion;
+use clap::{Parser, Subcommand};
+use url::Url;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
-struct Cli {}
+struct Cli {
+    /// Backend URL the installed extensions send the metrics to, the built-in one by default
+    #[arg(long, env = "OPTIMEIST_API_URL", value_parser = parse_api_url)]
+    api_url: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lists the memory changes the extension made to a function, the last 100 at most
+    History {
+        function_name: String,
+        /// SSM parameter the extension writes the audit records to, `/optimeist/<function>/audit` by default
+        #[arg(long)]
+        parameter_name: Option<String>,
+    },
+}
+
+/// Accepts HTTP(S) URLs without a query only, the trailing slash is removed.
+/// Matches the validation of OPTIMEIST_API_URL in the extension.
+fn parse_api_url(value: &str) -> Result<String, String> {
+    match Url::parse(value.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.query().is_none() => {
+            Ok(url.as_str().trim_end_matches('/').to_string())
+        }
+        _ => Err(format!("{value:?} is not an HTTP(S) URL without a query")),
+    }
+}
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
-    // Not used yet
-    let _args = Cli::parse();
+    let args = Cli::parse();
+
+    if let Some(Command::History {
+        function_name,
+        parameter_name,
+    }) = args.command
+    {
+        let aws_config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+        let parameter_name =
+            parameter_name.unwrap_or_else(|| aws::audit::default_parameter_name(&function_name));
+        let records = aws::audit::get_history(&aws_config, &parameter_name).await?;
+        aws::audit::print_history(&records);
+        return Ok(());
+    }
 
     let terminal = ratatui::init();
-    let result = App::new().run(terminal).await;
+    let result = App::new(args.api_url).run(terminal).await;
     ratatui::restore();
     result
 }
@@ -0,0 +1,352 @@
+use crate::environment::{AuditConfig, AuditSink, LambdaEnvironment};
+use crate::events::UpdaterEvent;
+use aws_sdk_ssm::types::ParameterType;
+use aws_sdk_ssm::Client as SsmClient;
+use eyre::eyre;
+use serde::Serialize;
+use tracing::{error, info};
+
+/// What made the updater change the memory size
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ChangeSource {
+    /// Recommendation of the backend or the local recommender
+    Poll,
+    OutOfMemory,
+    CanaryStart,
+    CanaryPromote,
+    CanaryRevert,
+    Rollback,
+}
+
+/// Memory change made by the extension
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord<'a> {
+    /// RFC 3339 time of the change
+    pub timestamp: String,
+    pub function_name: &'a str,
+    #[serde(rename = "oldMemorySizeMB")]
+    pub old_memory_size_mb: i32,
+    #[serde(rename = "newMemorySizeMB")]
+    pub new_memory_size_mb: i32,
+    pub strategy: String,
+    pub source: ChangeSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+    /// Execution environment that made the change
+    pub environment_id: &'a str,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// Describes the change behind the updater event, `None` if nothing was changed
+    pub fn from_event(environment: &'a LambdaEnvironment, event: &'a UpdaterEvent) -> Option<Self> {
+        let (old_memory_size_mb, new_memory_size_mb, source, reason, success, error) = match event {
+            UpdaterEvent::MemoryUpdate {
+                previous_memory_size_mb,
+                memory_size_mb,
+                applied,
+                canary,
+                error,
+                ..
+            } => (
+                *previous_memory_size_mb,
+                *memory_size_mb,
+                if *canary {
+                    ChangeSource::CanaryStart
+                } else {
+                    ChangeSource::Poll
+                },
+                None,
+                *applied,
+                error.as_deref(),
+            ),
+
+            // No attempt is made if the memory can't be raised
+            UpdaterEvent::OutOfMemory {
+                previous_memory_size_mb,
+                memory_size_mb,
+                ..
+            } if previous_memory_size_mb == memory_size_mb => return None,
+
+            UpdaterEvent::OutOfMemory {
+                previous_memory_size_mb,
+                memory_size_mb,
+                applied,
+                error,
+                ..
+            } => (
+                *previous_memory_size_mb,
+                *memory_size_mb,
+                ChangeSource::OutOfMemory,
+                None,
+                *applied,
+                error.as_deref(),
+            ),
+
+            UpdaterEvent::CanaryResult {
+                stable_memory_size_mb,
+                canary_memory_size_mb,
+                promoted: true,
+                error,
+                ..
+            } => (
+                *stable_memory_size_mb,
+                *canary_memory_size_mb,
+                ChangeSource::CanaryPromote,
+                None,
+                error.is_none(),
+                error.as_deref(),
+            ),
+
+            UpdaterEvent::CanaryResult {
+                stable_memory_size_mb,
+                canary_memory_size_mb,
+                reason,
+                error,
+                ..
+            } => (
+                *canary_memory_size_mb,
+                *stable_memory_size_mb,
+                ChangeSource::CanaryRevert,
+                reason.as_deref(),
+                error.is_none(),
+                error.as_deref(),
+            ),
+
+            UpdaterEvent::Rollback {
+                previous_memory_size_mb,
+                memory_size_mb,
+                reason,
+                applied,
+                error,
+                ..
+            } => (
+                *previous_memory_size_mb,
+                *memory_size_mb,
+                ChangeSource::Rollback,
+                Some(reason.as_str()),
+                *applied,
+                error.as_deref(),
+            ),
+
+            UpdaterEvent::Recommendation { .. } | UpdaterEvent::SuppressedUpdate { .. } => {
+                return None
+            }
+        };
+
+        Some(Self {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            function_name: &environment.name,
+            old_memory_size_mb,
+            new_memory_size_mb,
+            strategy: environment.strategy.to_string(),
+            source,
+            reason,
+            environment_id: &environment.environment_id,
+            success,
+            error,
+        })
+    }
+}
+
+/// Writes the audit records to the configured sink
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    config: AuditConfig,
+    ssm_client: SsmClient,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig, ssm_client: SsmClient) -> Self {
+        Self { config, ssm_client }
+    }
+
+    /// Records the change, failures are only logged
+    pub async fn record(&self, record: &AuditRecord<'_>) {
+        let Ok(value) = serde_json::to_string(record) else {
+            error!("Failed to serialize the audit record");
+            return;
+        };
+
+        // The log line is always written, CloudWatch keeps it even if the sink fails
+        info!(audit = %value, "Memory change");
+
+        let result = match self.config.sink {
+            AuditSink::Log => Ok(()),
+            AuditSink::Ssm => self.put_parameter(value).await,
+        };
+
+        if let Err(e) = result {
+            error!("Failed to write the audit record: {:?}", e);
+        }
+    }
+
+    /// Every record becomes a new version of the parameter, so the parameter history
+    /// is the audit trail
+    async fn put_parameter(&self, value: String) -> eyre::Result<()> {
+        self.ssm_client
+            .put_parameter()
+            .name(&self.config.parameter_name)
+            .value(value)
+            .r#type(ParameterType::String)
+            .overwrite(true)
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to update the audit parameter: {:?}", e))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RecommendationReason;
+
+    fn canary_result(promoted: bool, error: Option<&str>) -> UpdaterEvent {
+        UpdaterEvent::CanaryResult {
+            stable_version: "1".to_string(),
+            stable_memory_size_mb: 512,
+            canary_version: "2".to_string(),
+            canary_memory_size_mb: 256,
+            promoted,
+            reason: (!promoted).then(|| "p95 duration grew".to_string()),
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn memory_update_is_a_poll_or_a_canary_start() {
+        let environment = LambdaEnvironment::for_test();
+
+        for (canary, source) in [
+            (false, ChangeSource::Poll),
+            (true, ChangeSource::CanaryStart),
+        ] {
+            let event = UpdaterEvent::MemoryUpdate {
+                previous_memory_size_mb: 512,
+                memory_size_mb: 256,
+                applied: true,
+                canary,
+                published_version: None,
+                error: None,
+            };
+
+            let record = AuditRecord::from_event(&environment, &event).unwrap();
+
+            assert_eq!(record.source, source);
+            assert_eq!(record.old_memory_size_mb, 512);
+            assert_eq!(record.new_memory_size_mb, 256);
+            assert!(record.success);
+            assert_eq!(record.function_name, "test");
+        }
+    }
+
+    #[test]
+    fn out_of_memory_without_a_raise_is_not_a_change() {
+        let environment = LambdaEnvironment::for_test();
+
+        let event = UpdaterEvent::OutOfMemory {
+            request_id: "a".to_string(),
+            previous_memory_size_mb: 10240,
+            memory_size_mb: 10240,
+            applied: false,
+            published_version: None,
+            error: Some("already at the maximum".to_string()),
+        };
+
+        assert!(AuditRecord::from_event(&environment, &event).is_none());
+    }
+
+    #[test]
+    fn failed_out_of_memory_raise_keeps_the_error() {
+        let environment = LambdaEnvironment::for_test();
+
+        let event = UpdaterEvent::OutOfMemory {
+            request_id: "a".to_string(),
+            previous_memory_size_mb: 512,
+            memory_size_mb: 1024,
+            applied: false,
+            published_version: None,
+            error: Some("throttled".to_string()),
+        };
+
+        let record = AuditRecord::from_event(&environment, &event).unwrap();
+
+        assert_eq!(record.source, ChangeSource::OutOfMemory);
+        assert_eq!(record.new_memory_size_mb, 1024);
+        assert!(!record.success);
+        assert_eq!(record.error, Some("throttled"));
+    }
+
+    #[test]
+    fn canary_promotion_moves_to_the_canary_size() {
+        let environment = LambdaEnvironment::for_test();
+        let event = canary_result(true, None);
+
+        let record = AuditRecord::from_event(&environment, &event).unwrap();
+
+        assert_eq!(record.source, ChangeSource::CanaryPromote);
+        assert_eq!(record.old_memory_size_mb, 512);
+        assert_eq!(record.new_memory_size_mb, 256);
+        assert!(record.success);
+    }
+
+    #[test]
+    fn canary_revert_moves_back_to_the_stable_size() {
+        let environment = LambdaEnvironment::for_test();
+        let event = canary_result(false, Some("alias not found"));
+
+        let record = AuditRecord::from_event(&environment, &event).unwrap();
+
+        assert_eq!(record.source, ChangeSource::CanaryRevert);
+        assert_eq!(record.old_memory_size_mb, 256);
+        assert_eq!(record.new_memory_size_mb, 512);
+        assert_eq!(record.reason, Some("p95 duration grew"));
+        assert!(!record.success);
+        assert_eq!(record.error, Some("alias not found"));
+    }
+
+    #[test]
+    fn rollback_keeps_the_reason() {
+        let environment = LambdaEnvironment::for_test();
+
+        let event = UpdaterEvent::Rollback {
+            previous_memory_size_mb: 256,
+            memory_size_mb: 512,
+            reason: "error and timeout rate grew".to_string(),
+            pinned_until: 1_700_000_000,
+            applied: true,
+            error: None,
+        };
+
+        let record = AuditRecord::from_event(&environment, &event).unwrap();
+
+        assert_eq!(record.source, ChangeSource::Rollback);
+        assert_eq!(record.reason, Some("error and timeout rate grew"));
+        assert!(record.success);
+    }
+
+    #[test]
+    fn recommendations_are_not_changes() {
+        let environment = LambdaEnvironment::for_test();
+
+        let recommendation = UpdaterEvent::Recommendation {
+            current_memory_size_mb: 512,
+            memory_size_mb: 256,
+            reason: RecommendationReason::Poll,
+        };
+        let suppressed = UpdaterEvent::SuppressedUpdate {
+            current_memory_size_mb: 512,
+            memory_size_mb: 256,
+            reason: "cooldown".to_string(),
+        };
+
+        assert!(AuditRecord::from_event(&environment, &recommendation).is_none());
+        assert!(AuditRecord::from_event(&environment, &suppressed).is_none());
+    }
+}
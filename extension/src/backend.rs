@@ -0,0 +1,274 @@
+use crate::environment::{DecisionMode, LambdaEnvironment};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::Duration;
+use tracing::warn;
+
+/// Backend URL the extension is built with, used unless `OPTIMEIST_API_URL` overrides it
+pub const DEFAULT_API_URL: &str = env!("METRICS_API_URL");
+
+/// Timeout of a single request to the backend
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error returned by the backend client, classified by what the caller can do about it
+#[derive(Debug)]
+pub enum BackendError {
+    /// The request may succeed later: transport errors, 408, 429 and 5xx responses
+    Retryable {
+        status: Option<StatusCode>,
+        message: String,
+        /// Delay requested by the backend with the `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+    /// The access token is missing, invalid or has no access: 401 and 403 responses
+    Auth(StatusCode),
+    /// The request must not be repeated: other 4xx responses or an unexpected response body
+    Permanent {
+        status: Option<StatusCode>,
+        message: String,
+    },
+}
+
+impl BackendError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, BackendError::Retryable { .. })
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BackendError::Retryable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Classifies a completed HTTP exchange with a non-success status
+    async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after = parse_retry_after(&response);
+
+        // Keep the beginning of the body to make the logs useful, error pages may be huge
+        let message: String = response
+            .text()
+            .await
+            .unwrap_or_default()
+            .chars()
+            .take(200)
+            .collect();
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => BackendError::Auth(status),
+
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => {
+                BackendError::Retryable {
+                    status: Some(status),
+                    message,
+                    retry_after,
+                }
+            }
+
+            status if status.is_server_error() => BackendError::Retryable {
+                status: Some(status),
+                message,
+                retry_after,
+            },
+
+            status => BackendError::Permanent {
+                status: Some(status),
+                message,
+            },
+        }
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_builder() || e.is_decode() {
+            BackendError::Permanent {
+                status: e.status(),
+                message: e.to_string(),
+            }
+        } else {
+            BackendError::Retryable {
+                status: e.status(),
+                message: e.to_string(),
+                retry_after: None,
+            }
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Retryable {
+                status: Some(status),
+                message,
+                ..
+            } => write!(f, "Backend is unavailable ({status}): {message}"),
+            BackendError::Retryable { message, .. } => {
+                write!(f, "Backend is unavailable: {message}")
+            }
+            BackendError::Auth(status) => {
+                write!(f, "Backend rejected the access token ({status})")
+            }
+            BackendError::Permanent {
+                status: Some(status),
+                message,
+            } => write!(f, "Backend rejected the request ({status}): {message}"),
+            BackendError::Permanent { message, .. } => {
+                write!(f, "Backend rejected the request: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Defines how failed requests are repeated
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every next one
+    pub base_delay: Duration,
+    /// Upper bound of a delay, `Retry-After` values above it are not waited for
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter, so the environments don't retry in lockstep
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        let half = delay.as_millis() as u64 / 2;
+        Duration::from_millis(half + fastrand::u64(0..=half))
+    }
+}
+
+/// Client for the Optimeist backend API shared by the telemetry and updater tasks
+#[derive(Clone, Debug)]
+pub struct BackendClient {
+    client: reqwest::Client,
+    /// Backend URL without a trailing slash
+    base_url: String,
+    access_token: String,
+    retry: RetryPolicy,
+    /// The backend is not called at all in the local decision mode
+    enabled: bool,
+}
+
+impl BackendClient {
+    pub fn new(environment: &LambdaEnvironment) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: environment.api.base_url.clone(),
+            access_token: environment.access_token.clone(),
+            retry: environment.api.retry.clone(),
+            enabled: environment.decision_mode == DecisionMode::Remote,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns the full URL of the backend endpoint
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Sends a GET request and parses the JSON response
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, BackendError> {
+        let request = self.client.get(self.url(path)).query(query);
+        let response = self.execute(request).await?;
+
+        response
+            .json::<T>()
+            .await
+            .map_err(|e| BackendError::Permanent {
+                status: None,
+                message: format!("Failed to parse response: {e}"),
+            })
+    }
+
+    /// Sends a POST request with a JSON body, the response body is ignored
+    pub async fn post<B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<(), BackendError> {
+        let request = self.client.post(self.url(path)).json(body);
+        self.execute(request).await?;
+
+        Ok(())
+    }
+
+    /// Sends the request repeating it according to the retry policy
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, BackendError> {
+        let request = request
+            .timeout(REQUEST_TIMEOUT)
+            .bearer_auth(&self.access_token);
+
+        let mut attempt = 1;
+
+        loop {
+            let Some(request) = request.try_clone() else {
+                return Err(BackendError::Permanent {
+                    status: None,
+                    message: "Request can't be cloned".to_string(),
+                });
+            };
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => BackendError::from_response(response).await,
+                Err(e) => BackendError::from(e),
+            };
+
+            if !error.is_retryable() || attempt >= self.retry.max_attempts {
+                return Err(error);
+            }
+
+            let delay = match error.retry_after() {
+                // Don't block the caller for too long, the caller will try again later
+                Some(retry_after) if retry_after > self.retry.max_delay => return Err(error),
+                Some(retry_after) => retry_after,
+                None => self.retry.backoff(attempt),
+            };
+
+            warn!(
+                "Attempt {}/{} failed, retrying in {:?}: {}",
+                attempt, self.retry.max_attempts, delay, error
+            );
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Parses the `Retry-After` header given either in seconds or as an HTTP date
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
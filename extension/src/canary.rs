@@ -0,0 +1,404 @@
+use crate::environment::CanaryConfig;
+use crate::shared::SharedTable;
+use crate::stats::{FunctionStats, SizeStats};
+use aws_sdk_lambda::types::AliasRoutingConfiguration;
+use aws_sdk_lambda::Client as LambdaClient;
+use eyre::{eyre, Context, OptionExt, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{error, info};
+
+/// Canary rollout in progress: the alias points to the stable version
+/// and sends a share of the traffic to the canary one
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveCanary {
+    pub stable_version: String,
+    #[serde(rename = "stableMemorySizeMB")]
+    pub stable_memory_size_mb: i32,
+    pub canary_version: String,
+    #[serde(rename = "canaryMemorySizeMB")]
+    pub canary_memory_size_mb: i32,
+    /// Unix timestamp in seconds when the traffic was routed to the canary version
+    pub started_at: i64,
+}
+
+/// Keeps the canary rollout in progress and the stats of both versions in the lease table.
+///
+/// The environments add their invocations atomically, and find the rollout without querying
+/// the alias and the versions on every poll.
+#[derive(Clone, Debug)]
+pub struct CanaryStore {
+    table: SharedTable,
+}
+
+impl CanaryStore {
+    pub fn new(table: SharedTable) -> Self {
+        Self { table }
+    }
+
+    /// Adds the invocations of this environment to the stats of the rollout in progress.
+    /// Returns the rollout with the stats of all the environments, `None` if there is none.
+    pub async fn observe(
+        &self,
+        pending: &FunctionStats,
+    ) -> Result<Option<(ActiveCanary, FunctionStats)>> {
+        let Some(item) = self.table.load().await? else {
+            return Ok(None);
+        };
+
+        let canary: ActiveCanary =
+            serde_json::from_str(&item.state).wrap_err("Failed to parse the canary rollout")?;
+
+        // Only the sizes of both versions are compared
+        let sizes = [canary.stable_memory_size_mb, canary.canary_memory_size_mb];
+        let pending = FunctionStats {
+            sizes: pending
+                .sizes
+                .iter()
+                .filter(|(memory_size, _)| sizes.contains(memory_size))
+                .map(|(memory_size, stats)| (*memory_size, stats.clone()))
+                .collect(),
+        };
+
+        let item = if pending.is_empty() {
+            item
+        } else {
+            match self.table.add(&item.epoch, &pending.to_counters()).await? {
+                Some(item) => item,
+                None => return Ok(None),
+            }
+        };
+
+        Ok(Some((canary, FunctionStats::from_counters(&item.counters))))
+    }
+
+    /// Starts collecting the stats of the rollout, replacing the previous one
+    pub async fn start(&self, canary: &ActiveCanary) -> Result<()> {
+        self.table
+            .start(&canary.canary_version, serde_json::to_string(canary)?)
+            .await
+    }
+
+    /// Ends the rollout in progress if there is one, e.g. after a memory change moved the alias
+    /// away from it. Returns the ended rollout.
+    pub async fn abort(&self) -> Result<Option<ActiveCanary>> {
+        let Some(item) = self.table.load().await? else {
+            return Ok(None);
+        };
+
+        let canary: ActiveCanary =
+            serde_json::from_str(&item.state).wrap_err("Failed to parse the canary rollout")?;
+
+        Ok(self.finish(&canary).await?.then_some(canary))
+    }
+
+    /// Ends the rollout. Returns `false` if another environment has already ended it.
+    pub async fn finish(&self, canary: &ActiveCanary) -> Result<bool> {
+        self.table.remove(&canary.canary_version).await
+    }
+}
+
+/// Outcome of comparing the canary version with the stable one
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    /// Not enough invocations of one of the versions yet
+    Pending,
+    Promote,
+    Revert(String),
+}
+
+/// Publishes the updated configuration as a new version and routes a share
+/// of the alias traffic to it. Returns the started rollout.
+pub async fn start(
+    client: &LambdaClient,
+    store: &CanaryStore,
+    function_name: &str,
+    alias: &str,
+    weight: f64,
+    memory_size_mb: i32,
+) -> Result<ActiveCanary> {
+    let version = client
+        .publish_version()
+        .function_name(function_name)
+        .description("Memory size canary by Optimeist")
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to publish a canary version: {:?}", e))?
+        .version
+        .ok_or_eyre("Failed to get the published version")?;
+
+    let stable_version = client
+        .get_alias()
+        .function_name(function_name)
+        .name(alias)
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to get alias {}: {:?}", alias, e))?
+        .function_version
+        .ok_or_eyre("Failed to get the alias version")?;
+
+    let stable_memory_size_mb = client
+        .get_function()
+        .function_name(function_name)
+        .qualifier(&stable_version)
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to get version {}: {:?}", stable_version, e))?
+        .configuration
+        .and_then(|configuration| configuration.memory_size)
+        .ok_or_eyre("Failed to get the stable memory size")?;
+
+    let canary = ActiveCanary {
+        stable_version,
+        stable_memory_size_mb,
+        canary_version: version,
+        canary_memory_size_mb: memory_size_mb,
+        started_at: chrono::Utc::now().timestamp(),
+    };
+
+    // Stored before routing, so no canary traffic is left without being evaluated
+    store.start(&canary).await?;
+
+    info!(
+        "Routing {}% of alias {} traffic to version {}",
+        weight * 100.0,
+        alias,
+        canary.canary_version
+    );
+
+    let result = client
+        .update_alias()
+        .function_name(function_name)
+        .name(alias)
+        .routing_config(
+            AliasRoutingConfiguration::builder()
+                .additional_version_weights(&canary.canary_version, weight)
+                .build(),
+        )
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        if let Err(e) = store.finish(&canary).await {
+            error!("Failed to remove the canary rollout: {:?}", e);
+        }
+
+        return Err(eyre!(
+            "Failed to route traffic to version {}: {:?}",
+            canary.canary_version,
+            e
+        ));
+    }
+
+    Ok(canary)
+}
+
+/// Compares the stats of both versions collected during the canary rollout
+pub fn evaluate(
+    config: &CanaryConfig,
+    canary: &ActiveCanary,
+    stats: &FunctionStats,
+    now: i64,
+) -> Verdict {
+    let empty = SizeStats::default();
+    let stable_stats = stats
+        .sizes
+        .get(&canary.stable_memory_size_mb)
+        .unwrap_or(&empty);
+    let canary_stats = stats
+        .sizes
+        .get(&canary.canary_memory_size_mb)
+        .unwrap_or(&empty);
+
+    // Running out of memory is a reason to revert right away
+    if canary_stats.out_of_memory > 0 {
+        return Verdict::Revert(format!(
+            "{} invocations of the canary ran out of memory",
+            canary_stats.out_of_memory
+        ));
+    }
+
+    if stable_stats.invocations < config.min_samples
+        || canary_stats.invocations < config.min_samples
+    {
+        if now - canary.started_at < config.max_duration.as_secs() as i64 {
+            return Verdict::Pending;
+        }
+
+        return Verdict::Revert(format!(
+            "not enough invocations after {:?}: {} stable and {} canary",
+            config.max_duration, stable_stats.invocations, canary_stats.invocations
+        ));
+    }
+
+    let error_rate_increase = canary_stats.failure_rate() - stable_stats.failure_rate();
+
+    if error_rate_increase > config.max_error_rate_increase {
+        return Verdict::Revert(format!(
+            "error rate is {:.2}% with the canary and {:.2}% with the stable version",
+            canary_stats.failure_rate() * 100.0,
+            stable_stats.failure_rate() * 100.0
+        ));
+    }
+
+    let stable_duration = stable_stats.mean_duration_ms();
+    let canary_duration = canary_stats.mean_duration_ms();
+
+    if canary_duration > stable_duration * (1.0 + config.max_duration_increase) {
+        return Verdict::Revert(format!(
+            "mean duration is {:.1} ms with the canary and {:.1} ms with the stable version",
+            canary_duration, stable_duration
+        ));
+    }
+
+    Verdict::Promote
+}
+
+/// Moves the whole alias traffic to the canary version
+pub async fn promote(
+    client: &LambdaClient,
+    function_name: &str,
+    alias: &str,
+    canary: &ActiveCanary,
+) -> Result<()> {
+    client
+        .update_alias()
+        .function_name(function_name)
+        .name(alias)
+        .function_version(&canary.canary_version)
+        .routing_config(no_routing())
+        .send()
+        .await
+        .map_err(|e| {
+            eyre!(
+                "Failed to promote version {}: {:?}",
+                canary.canary_version,
+                e
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Moves the whole alias traffic back to the stable version
+pub async fn revert(client: &LambdaClient, function_name: &str, alias: &str) -> Result<()> {
+    client
+        .update_alias()
+        .function_name(function_name)
+        .name(alias)
+        .routing_config(no_routing())
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to revert alias {}: {:?}", alias, e))?;
+
+    Ok(())
+}
+
+/// Routing configuration without additional versions, ends a canary rollout
+pub fn no_routing() -> AliasRoutingConfiguration {
+    AliasRoutingConfiguration::builder()
+        .set_additional_version_weights(Some(HashMap::new()))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn config() -> CanaryConfig {
+        CanaryConfig {
+            weight: 0.1,
+            min_samples: 10,
+            max_duration: Duration::from_secs(3600),
+            max_error_rate_increase: 0.01,
+            max_duration_increase: 0.5,
+        }
+    }
+
+    fn canary(age_seconds: i64) -> ActiveCanary {
+        ActiveCanary {
+            stable_version: "1".to_string(),
+            stable_memory_size_mb: 1024,
+            canary_version: "2".to_string(),
+            canary_memory_size_mb: 512,
+            started_at: NOW - age_seconds,
+        }
+    }
+
+    fn size_stats(invocations: u64, errors: u64, duration_ms: f64) -> SizeStats {
+        SizeStats {
+            invocations,
+            errors,
+            duration_ms_sum: duration_ms * invocations as f64,
+            billed_duration_ms_sum: duration_ms * invocations as f64,
+            ..SizeStats::default()
+        }
+    }
+
+    fn stats(stable: SizeStats, canary: SizeStats) -> FunctionStats {
+        FunctionStats {
+            sizes: [(1024, stable), (512, canary)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn pending_without_enough_invocations() {
+        let stats = stats(size_stats(100, 0, 100.0), size_stats(5, 0, 100.0));
+        assert_eq!(
+            evaluate(&config(), &canary(60), &stats, NOW),
+            Verdict::Pending
+        );
+    }
+
+    #[test]
+    fn reverts_without_enough_invocations_after_the_max_duration() {
+        let stats = stats(size_stats(100, 0, 100.0), size_stats(5, 0, 100.0));
+        assert!(matches!(
+            evaluate(&config(), &canary(3600), &stats, NOW),
+            Verdict::Revert(reason) if reason.starts_with("not enough invocations")
+        ));
+    }
+
+    #[test]
+    fn promotes_a_comparable_canary() {
+        let stats = stats(size_stats(100, 0, 100.0), size_stats(20, 0, 120.0));
+        assert_eq!(
+            evaluate(&config(), &canary(60), &stats, NOW),
+            Verdict::Promote
+        );
+    }
+
+    #[test]
+    fn reverts_out_of_memory_right_away() {
+        let mut canary_stats = size_stats(1, 1, 100.0);
+        canary_stats.out_of_memory = 1;
+
+        let stats = stats(SizeStats::default(), canary_stats);
+        assert!(matches!(
+            evaluate(&config(), &canary(10), &stats, NOW),
+            Verdict::Revert(reason) if reason.contains("ran out of memory")
+        ));
+    }
+
+    #[test]
+    fn reverts_when_the_error_rate_grows() {
+        let stats = stats(size_stats(100, 0, 100.0), size_stats(50, 5, 100.0));
+        assert!(matches!(
+            evaluate(&config(), &canary(60), &stats, NOW),
+            Verdict::Revert(reason) if reason.starts_with("error rate")
+        ));
+    }
+
+    #[test]
+    fn reverts_when_the_duration_grows() {
+        let stats = stats(size_stats(100, 0, 100.0), size_stats(50, 0, 200.0));
+        assert!(matches!(
+            evaluate(&config(), &canary(60), &stats, NOW),
+            Verdict::Revert(reason) if reason.starts_with("mean duration")
+        ));
+    }
+}
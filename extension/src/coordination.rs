@@ -0,0 +1,255 @@
+use crate::environment::{CoordinationConfig, LambdaEnvironment};
+use crate::shared::SharedTable;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use eyre::{eyre, Result};
+use tracing::{info, warn};
+
+/// Outcome of taking the update lease
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lease {
+    Acquired,
+    /// Another environment is changing the memory size to this size or a larger one
+    Held {
+        memory_size_mb: i32,
+    },
+}
+
+/// Grants a single execution environment the right to change the memory size,
+/// so the concurrent environments don't repeat the same update.
+///
+/// The right is a lease kept in a DynamoDB table with the `functionArn` string partition key.
+/// It's taken with a conditional write and expires on its own if the owner is gone.
+#[derive(Clone, Debug)]
+pub struct Coordinator {
+    client: DynamoClient,
+    config: CoordinationConfig,
+    table_name: String,
+    function_arn: String,
+    environment_id: String,
+}
+
+impl Coordinator {
+    /// Returns `None` if the coordination is not configured
+    pub fn new(aws_config: &SdkConfig, environment: &LambdaEnvironment) -> Option<Self> {
+        let config = environment.coordination.clone();
+        let table_name = config.table_name.clone()?;
+
+        let mut builder = aws_sdk_dynamodb::config::Builder::from(aws_config);
+
+        // A local DynamoDB stand-in can be used instead of the real service
+        if let Some(endpoint_url) = &config.endpoint_url {
+            builder = builder.endpoint_url(endpoint_url);
+        }
+
+        Some(Self {
+            client: DynamoClient::from_conf(builder.build()),
+            config,
+            table_name,
+            function_arn: environment.arn.clone(),
+            environment_id: environment.environment_id.clone(),
+        })
+    }
+
+    /// Takes the lease to change the memory size. A lease for a smaller size is taken over,
+    /// so raising the memory after an out-of-memory error is never held up by a downsize.
+    pub async fn acquire(&self, memory_size_mb: i32) -> Result<Lease> {
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = now + self.config.lease_duration.as_secs() as i64;
+
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("functionArn", AttributeValue::S(self.function_arn.clone()))
+            .item("owner", AttributeValue::S(self.environment_id.clone()))
+            .item(
+                "memorySizeMB",
+                AttributeValue::N(memory_size_mb.to_string()),
+            )
+            .item("expiresAt", AttributeValue::N(expires_at.to_string()))
+            // The owner may extend its own lease
+            .condition_expression(
+                "attribute_not_exists(functionArn) OR expiresAt < :now OR #owner = :owner \
+                OR #memory < :memory",
+            )
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_names("#memory", "memorySizeMB")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .expression_attribute_values(":owner", AttributeValue::S(self.environment_id.clone()))
+            .expression_attribute_values(":memory", AttributeValue::N(memory_size_mb.to_string()))
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .send()
+            .await;
+
+        match result.map_err(SdkError::into_service_error) {
+            Ok(_) => {
+                info!(
+                    "Acquired the lease to change the memory size to {} MB",
+                    memory_size_mb
+                );
+                Ok(Lease::Acquired)
+            }
+            Err(PutItemError::ConditionalCheckFailedException(e)) => {
+                let memory_size_mb = e
+                    .item()
+                    .and_then(|item| item.get("memorySizeMB"))
+                    .and_then(|value| value.as_n().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_default();
+
+                Ok(Lease::Held { memory_size_mb })
+            }
+            Err(e) => Err(eyre!("Failed to acquire the update lease: {:?}", e)),
+        }
+    }
+
+    /// Returns the item of the lease table keeping the shared state with the given name
+    pub fn shared(&self, name: &str) -> SharedTable {
+        SharedTable::new(
+            self.client.clone(),
+            self.table_name.clone(),
+            format!("{}#{}", self.function_arn, name),
+        )
+    }
+
+    /// Gives the lease back after a failed change, so another environment can try it
+    pub async fn release(&self) {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.function_arn.clone()))
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(":owner", AttributeValue::S(self.environment_id.clone()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            warn!("Failed to release the update lease: {:?}", e);
+        }
+    }
+}
+
+/// Runs against a local DynamoDB stand-in, e.g. `docker run -p 8000:8000 amazon/dynamodb-local`
+/// with `OPTIMEIST_DYNAMODB_ENDPOINT_URL=http://localhost:8000`, skipped without it
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+    use aws_sdk_dynamodb::types::{
+        AttributeDefinition, BillingMode, KeySchemaElement, KeyType, ScalarAttributeType,
+    };
+    use std::time::Duration;
+
+    const FUNCTION_ARN: &str = "arn:aws:lambda:us-east-1:123456789012:function:test";
+
+    async fn create_table(endpoint_url: &str) -> (DynamoClient, String) {
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(endpoint_url)
+            .build();
+
+        let client = DynamoClient::from_conf(config);
+        let table_name = format!("optimeist-leases-{}", fastrand::u32(..));
+
+        client
+            .create_table()
+            .table_name(&table_name)
+            .attribute_definitions(
+                AttributeDefinition::builder()
+                    .attribute_name("functionArn")
+                    .attribute_type(ScalarAttributeType::S)
+                    .build()
+                    .unwrap(),
+            )
+            .key_schema(
+                KeySchemaElement::builder()
+                    .attribute_name("functionArn")
+                    .key_type(KeyType::Hash)
+                    .build()
+                    .unwrap(),
+            )
+            .billing_mode(BillingMode::PayPerRequest)
+            .send()
+            .await
+            .expect("Failed to create the lease table");
+
+        (client, table_name)
+    }
+
+    fn coordinator(
+        client: &DynamoClient,
+        table_name: &str,
+        environment_id: &str,
+        lease_duration: Duration,
+    ) -> Coordinator {
+        Coordinator {
+            client: client.clone(),
+            config: CoordinationConfig {
+                table_name: Some(table_name.to_string()),
+                endpoint_url: None,
+                lease_duration,
+            },
+            table_name: table_name.to_string(),
+            function_arn: FUNCTION_ARN.to_string(),
+            environment_id: environment_id.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn lease_lifecycle() {
+        let Ok(endpoint_url) = std::env::var("OPTIMEIST_DYNAMODB_ENDPOINT_URL") else {
+            eprintln!("OPTIMEIST_DYNAMODB_ENDPOINT_URL is not set, skipping");
+            return;
+        };
+
+        let (client, table_name) = create_table(&endpoint_url).await;
+        let lease = Duration::from_secs(300);
+        let first = coordinator(&client, &table_name, "first", lease);
+        let second = coordinator(&client, &table_name, "second", lease);
+
+        let held = |memory_size_mb| Lease::Held { memory_size_mb };
+
+        // Acquire and conflict
+        assert_eq!(first.acquire(1024).await.unwrap(), Lease::Acquired);
+        assert_eq!(second.acquire(512).await.unwrap(), held(1024));
+        assert_eq!(second.acquire(1024).await.unwrap(), held(1024));
+
+        // The owner extends its own lease
+        assert_eq!(first.acquire(1024).await.unwrap(), Lease::Acquired);
+
+        // A larger size takes the lease over
+        assert_eq!(second.acquire(1536).await.unwrap(), Lease::Acquired);
+        assert_eq!(first.acquire(1024).await.unwrap(), held(1536));
+
+        // Only the owner releases the lease
+        first.release().await;
+        assert_eq!(first.acquire(1024).await.unwrap(), held(1536));
+
+        second.release().await;
+        assert_eq!(first.acquire(1024).await.unwrap(), Lease::Acquired);
+
+        // An expired lease is taken over
+        let expiring = coordinator(&client, &table_name, "expiring", Duration::from_secs(1));
+        first.release().await;
+        assert_eq!(expiring.acquire(512).await.unwrap(), Lease::Acquired);
+        assert_eq!(first.acquire(512).await.unwrap(), held(512));
+
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        assert_eq!(first.acquire(512).await.unwrap(), Lease::Acquired);
+
+        client
+            .delete_table()
+            .table_name(&table_name)
+            .send()
+            .await
+            .expect("Failed to delete the lease table");
+    }
+}
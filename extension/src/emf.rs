@@ -0,0 +1,183 @@
+use crate::audit::AuditRecord;
+use crate::environment::LambdaEnvironment;
+use crate::events::UpdaterEvent;
+use crate::sink::{SinkKind, TelemetrySink};
+use crate::telemetry::{Batch, Metrics};
+use futures::future::BoxFuture;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use tracing::error;
+
+/// Dimensions of every metric, CloudWatch aggregates the metrics by all of them
+const DIMENSIONS: [&str; 3] = ["FunctionName", "Version", "Strategy"];
+
+/// Builds a CloudWatch Embedded Metric Format log line
+struct EmfLine<'a> {
+    environment: &'a LambdaEnvironment,
+    version: &'a str,
+    /// Unix timestamp in milliseconds the metrics are recorded at
+    timestamp_ms: i64,
+    definitions: Vec<Value>,
+    fields: Map<String, Value>,
+}
+
+impl<'a> EmfLine<'a> {
+    fn new(environment: &'a LambdaEnvironment, version: &'a str) -> Self {
+        Self {
+            environment,
+            version,
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            definitions: vec![],
+            fields: Map::new(),
+        }
+    }
+
+    /// Records the metrics at the time they happened instead of when the line is written
+    fn at(mut self, timestamp_ms: i64) -> Self {
+        self.timestamp_ms = timestamp_ms;
+        self
+    }
+
+    fn metric(mut self, name: &str, unit: &str, value: impl Into<Value>) -> Self {
+        self.definitions.push(json!({ "Name": name, "Unit": unit }));
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Adds a field that is searchable in the logs but is not a metric
+    fn property(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+
+    fn build(self) -> String {
+        let mut line = self.fields;
+
+        line.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": self.timestamp_ms,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.environment.telemetry.emf_namespace,
+                    "Dimensions": [DIMENSIONS],
+                    "Metrics": self.definitions,
+                }],
+            }),
+        );
+        line.insert("FunctionName".to_string(), json!(self.environment.name));
+        line.insert("Version".to_string(), json!(self.version));
+        line.insert(
+            "Strategy".to_string(),
+            json!(self.environment.strategy.to_string()),
+        );
+
+        Value::Object(line).to_string()
+    }
+}
+
+/// Writes the invocation metrics to stdout in the CloudWatch Embedded Metric Format
+#[derive(Debug)]
+pub struct EmfSink {
+    pub environment: LambdaEnvironment,
+}
+
+impl TelemetrySink for EmfSink {
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let lines: Vec<String> = batch
+                .metrics
+                .iter()
+                .map(|metrics| invocation_line(&self.environment, metrics))
+                .collect();
+
+            write_lines(&lines);
+        })
+    }
+}
+
+fn invocation_line(environment: &LambdaEnvironment, metrics: &Metrics) -> String {
+    let version = metrics.version.as_deref().unwrap_or(&environment.version);
+
+    let mut line = EmfLine::new(environment, version)
+        .metric("Duration", "Milliseconds", metrics.duration_ms)
+        .metric("BilledDuration", "Milliseconds", metrics.billed_duration_ms)
+        .metric("MaxMemoryUsed", "Megabytes", metrics.max_memory_used_mb)
+        .metric("MemorySize", "Megabytes", metrics.memory_size_mb)
+        .property("RequestId", metrics.request_id.as_str());
+
+    if let Some(init_duration_ms) = metrics.init_duration_ms {
+        line = line.metric("InitDuration", "Milliseconds", init_duration_ms);
+    }
+
+    // The invocation may have ended a while before the batch is written
+    if let Ok(timestamp_us) = metrics.timestamp_us.parse::<i64>() {
+        line = line.at(timestamp_us / 1000);
+    }
+
+    line.build()
+}
+
+/// Writes the recommendations and the memory changes of the updater in the EMF,
+/// if the EMF sink is enabled
+pub fn write_event(environment: &LambdaEnvironment, event: &UpdaterEvent) {
+    if !environment.telemetry.sinks.contains(&SinkKind::Emf) {
+        return;
+    }
+
+    let line = EmfLine::new(environment, &environment.version);
+
+    let line = match event {
+        UpdaterEvent::Recommendation {
+            current_memory_size_mb,
+            memory_size_mb,
+            reason,
+        } => line
+            .metric("RecommendedMemorySize", "Megabytes", *memory_size_mb)
+            .property("CurrentMemorySize", *current_memory_size_mb)
+            .property("Reason", json!(reason)),
+
+        UpdaterEvent::SuppressedUpdate {
+            current_memory_size_mb,
+            memory_size_mb,
+            reason,
+        } => line
+            .metric("SuppressedMemoryChange", "Count", 1)
+            .metric("RecommendedMemorySize", "Megabytes", *memory_size_mb)
+            .property("CurrentMemorySize", *current_memory_size_mb)
+            .property("Reason", reason.as_str()),
+
+        event => {
+            let Some(record) = AuditRecord::from_event(environment, event) else {
+                return;
+            };
+
+            line.metric("MemoryChange", "Count", 1)
+                .metric(
+                    "FailedMemoryChange",
+                    "Count",
+                    if record.success { 0 } else { 1 },
+                )
+                .metric("NewMemorySize", "Megabytes", record.new_memory_size_mb)
+                .property("PreviousMemorySize", record.old_memory_size_mb)
+                .property("Source", json!(record.source))
+        }
+    };
+
+    write_lines(&[line.build()]);
+}
+
+fn write_lines(lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+
+    // Locked once, so the lines are not interleaved with the logs
+    let mut stdout = std::io::stdout().lock();
+
+    for line in lines {
+        if let Err(e) = writeln!(stdout, "{line}") {
+            error!("Failed to write the EMF metrics: {:?}", e);
+            return;
+        }
+    }
+}
@@ -1,12 +1,21 @@
+use crate::backend::{RetryPolicy, DEFAULT_API_URL};
+use crate::guardrails::{LAMBDA_MAX_MEMORY_MB, LAMBDA_MIN_MEMORY_MB};
+use crate::sink::SinkKind;
+use crate::spool::SpoolEviction;
+use crate::uploader::OverflowPolicy;
 use aws_config::SdkConfig;
 use aws_sdk_lambda::Client as LambdaClient;
 use aws_sdk_secretsmanager::Client as SecretsClient;
 use eyre::{Context, OptionExt, Result};
-use serde::Serialize;
+use percent_encoding::percent_decode_str;
+use serde::{Serialize, Serializer};
 use std::env;
 use std::fmt;
 use std::fmt::Formatter;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+use tracing::{error, warn};
 
 /// Contains information about the AWS Lambda function environment
 #[derive(Clone, Debug, Serialize)]
@@ -18,21 +27,619 @@ pub struct LambdaEnvironment {
     pub name: String,
     pub memory_size_mb: i32,
     pub strategy: Strategy,
+    pub decision_mode: DecisionMode,
+    pub update_mode: UpdateMode,
+    /// Random identifier of this execution environment
+    pub environment_id: String,
 
     #[serde(skip)]
     pub memory_parameter_name: Option<String>,
 
+    /// Alias moved to a new version published after every memory change, the opt-in publish mode
+    pub publish_alias: Option<String>,
+
     #[serde(skip)]
     pub access_token: String,
+
+    #[serde(skip)]
+    pub telemetry: TelemetryConfig,
+
+    #[serde(skip)]
+    pub api: ApiConfig,
+
+    #[serde(skip)]
+    pub oom: OomConfig,
+
+    #[serde(skip)]
+    pub local: LocalConfig,
+
+    #[serde(skip)]
+    pub guardrails: GuardrailsConfig,
+
+    #[serde(skip)]
+    pub coordination: CoordinationConfig,
+
+    #[serde(skip)]
+    pub poll: PollConfig,
+
+    #[serde(skip)]
+    pub canary: CanaryConfig,
+
+    #[serde(skip)]
+    pub rollback: RollbackConfig,
+
+    #[serde(skip)]
+    pub audit: AuditConfig,
+
+    #[serde(skip)]
+    pub throttle: ThrottleConfig,
+
+    #[serde(skip)]
+    pub otlp: OtlpConfig,
+}
+
+/// Controls the OTLP/HTTP export of the metrics to an OpenTelemetry collector.
+/// Reads the standard OpenTelemetry env variables.
+#[derive(Clone, Debug)]
+pub struct OtlpConfig {
+    /// URL the metrics are posted to, a collector running next to the function by default
+    pub endpoint: String,
+    /// Headers added to every export request, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+}
+
+impl OtlpConfig {
+    pub fn from_env() -> Self {
+        let endpoint = env::var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT").unwrap_or_else(|_| {
+            let base = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .unwrap_or_else(|_| "http://localhost:4318".to_string());
+            format!("{}/v1/metrics", base.trim_end_matches('/'))
+        });
+
+        Self {
+            endpoint,
+            headers: otlp_headers_from_env(),
+        }
+    }
+}
+
+/// Reads the headers written as comma-separated `name=value` pairs
+fn otlp_headers_from_env() -> Vec<(String, String)> {
+    let value = env::var("OTEL_EXPORTER_OTLP_METRICS_HEADERS")
+        .or_else(|_| env::var("OTEL_EXPORTER_OTLP_HEADERS"))
+        .unwrap_or_default();
+
+    parse_otlp_headers(&value)
+}
+
+/// Parses the headers, the values are percent-decoded as the OpenTelemetry spec requires.
+/// Invalid pairs are skipped.
+pub fn parse_otlp_headers(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .filter_map(|pair| {
+            let Some((name, value)) = pair.split_once('=') else {
+                warn!("Invalid OTLP header, expected name=value: {:?}", pair);
+                return None;
+            };
+
+            match percent_decode_str(value.trim()).decode_utf8() {
+                Ok(value) => Some((name.trim().to_string(), value.into_owned())),
+                Err(e) => {
+                    warn!("Invalid OTLP header value of {}: {}", name.trim(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Limits how often the memory size is changed, every change causes a wave of cold starts.
+/// The limits are opt-in: tracking the changes needs `ssm:GetParameter` and `ssm:PutParameter`
+/// on the changes parameter, granted by the installers only when a limit is set.
+#[derive(Clone, Debug)]
+pub struct ThrottleConfig {
+    /// Minimum time between two changes, zero disables the cooldown
+    pub cooldown: Duration,
+    /// Maximum number of changes during the last day, zero means no limit
+    pub max_changes_per_day: u32,
+    /// Number of consecutive polls that must recommend the same memory size
+    pub stable_polls: u32,
+    /// SSM parameter to share the applied changes between the execution environments
+    pub parameter_name: String,
+}
+
+impl ThrottleConfig {
+    pub fn from_env(function_name: &str) -> Self {
+        Self {
+            cooldown: Duration::from_secs(parse_env("OPTIMEIST_UPDATE_COOLDOWN_SECONDS", 0)),
+            max_changes_per_day: parse_env("OPTIMEIST_MAX_CHANGES_PER_DAY", 0),
+            stable_polls: parse_env("OPTIMEIST_STABLE_POLLS", 1).max(1),
+            parameter_name: parse_env(
+                "OPTIMEIST_CHANGES_PARAMETER_NAME",
+                format!("/optimeist/{function_name}/changes"),
+            ),
+        }
+    }
+
+    /// Returns `true` if the applied changes have to be tracked
+    pub fn is_enabled(&self) -> bool {
+        !self.cooldown.is_zero() || self.max_changes_per_day > 0
+    }
+}
+
+/// Controls where the audit records of the memory changes are written
+#[derive(Clone, Debug)]
+pub struct AuditConfig {
+    pub sink: AuditSink,
+    /// SSM parameter whose history keeps the audit records in the SSM sink
+    pub parameter_name: String,
+}
+
+impl AuditConfig {
+    pub fn from_env(function_name: &str) -> Self {
+        Self {
+            sink: AuditSink::from_env(),
+            parameter_name: parse_env(
+                "OPTIMEIST_AUDIT_PARAMETER_NAME",
+                format!("/optimeist/{function_name}/audit"),
+            ),
+        }
+    }
+}
+
+/// Destination of the audit records, the log line is written for every sink
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuditSink {
+    /// Records are only written to the function logs
+    #[default]
+    Log,
+    /// Every record is a new version of an SSM parameter
+    Ssm,
+}
+
+impl fmt::Display for AuditSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSink::Log => write!(f, "LOG"),
+            AuditSink::Ssm => write!(f, "SSM"),
+        }
+    }
+}
+
+impl FromStr for AuditSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LOG" => Ok(AuditSink::Log),
+            "SSM" => Ok(AuditSink::Ssm),
+            _ => Err(format!("Unknown audit sink: {s}")),
+        }
+    }
+}
+
+impl AuditSink {
+    fn from_env() -> Self {
+        let Ok(value) = env::var("OPTIMEIST_AUDIT_SINK") else {
+            return AuditSink::default();
+        };
+
+        value.parse().unwrap_or_else(|e| {
+            error!(
+                "Invalid OPTIMEIST_AUDIT_SINK: {}. Falling back to {}",
+                e,
+                AuditSink::default()
+            );
+            AuditSink::default()
+        })
+    }
+}
+
+/// Controls the regression check after a memory change and the rollback of bad changes
+#[derive(Clone, Debug)]
+pub struct RollbackConfig {
+    /// Time the invocations are observed after a change, zero disables rollbacks
+    pub window: Duration,
+    /// Number of invocations before and after the change required to compare them
+    pub min_samples: u64,
+    /// Largest acceptable growth of the p95 duration, e.g. 0.2 is 20%
+    pub max_p95_increase: f64,
+    /// Largest acceptable growth of the error and timeout rate, e.g. 0.01 is 1 percentage point
+    pub max_error_rate_increase: f64,
+    /// Time the previous memory size is kept after a rollback
+    pub cooldown: Duration,
+}
+
+impl RollbackConfig {
+    pub fn from_env() -> Self {
+        Self {
+            window: Duration::from_secs(parse_env("OPTIMEIST_ROLLBACK_WINDOW_SECONDS", 0)),
+            min_samples: parse_env("OPTIMEIST_ROLLBACK_MIN_SAMPLES", 30).max(1),
+            max_p95_increase: parse_env("OPTIMEIST_ROLLBACK_MAX_P95_INCREASE", 0.2),
+            max_error_rate_increase: parse_env("OPTIMEIST_ROLLBACK_MAX_ERROR_RATE_INCREASE", 0.01),
+            cooldown: Duration::from_secs(parse_env(
+                "OPTIMEIST_ROLLBACK_COOLDOWN_SECONDS",
+                24 * 60 * 60,
+            )),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+}
+
+/// Controls the canary rollout of new memory sizes through the publish alias
+#[derive(Clone, Debug)]
+pub struct CanaryConfig {
+    /// Share of the alias traffic routed to the canary version, zero disables canaries
+    pub weight: f64,
+    /// Number of invocations of each version required to compare them
+    pub min_samples: u64,
+    /// Canary is reverted if there are not enough invocations after this time
+    pub max_duration: Duration,
+    /// Largest acceptable growth of the error rate, e.g. 0.01 is 1 percentage point
+    pub max_error_rate_increase: f64,
+    /// Largest acceptable growth of the mean duration relative to the stable version
+    pub max_duration_increase: f64,
+}
+
+impl CanaryConfig {
+    pub fn from_env() -> Self {
+        let mut weight = parse_env("OPTIMEIST_CANARY_WEIGHT", 0.0_f64);
+
+        // Lambda only accepts weights below 1 for the additional version
+        if !(0.0..1.0).contains(&weight) {
+            warn!(
+                "Invalid OPTIMEIST_CANARY_WEIGHT: {}, expected a number from 0 to 1. Canary is disabled",
+                weight
+            );
+            weight = 0.0;
+        }
+
+        Self {
+            weight,
+            min_samples: parse_env("OPTIMEIST_CANARY_MIN_SAMPLES", 50).max(1),
+            max_duration: Duration::from_secs(parse_env(
+                "OPTIMEIST_CANARY_MAX_DURATION_SECONDS",
+                3600,
+            )),
+            max_error_rate_increase: parse_env("OPTIMEIST_CANARY_MAX_ERROR_RATE_INCREASE", 0.01),
+            max_duration_increase: parse_env("OPTIMEIST_CANARY_MAX_DURATION_INCREASE", 0.5),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.weight > 0.0
+    }
+}
+
+/// Controls how often the updater polls for a new memory size
+#[derive(Clone, Debug)]
+pub struct PollConfig {
+    /// Minimum time between polls
+    pub interval: Duration,
+    /// Upper bound of a random delay added to the interval
+    pub max_jitter: Duration,
+    /// Longest time an invocation is held open for the poll, the rest of the work
+    /// continues in the background
+    pub budget: Duration,
+}
+
+impl PollConfig {
+    pub fn from_env() -> Self {
+        Self {
+            interval: Duration::from_secs(parse_env("OPTIMEIST_POLL_INTERVAL_SECONDS", 300)),
+            max_jitter: Duration::from_secs(parse_env("OPTIMEIST_POLL_JITTER_SECONDS", 30)),
+            budget: Duration::from_millis(parse_env("OPTIMEIST_POLL_BUDGET_MS", 500)),
+        }
+    }
+
+    /// Returns a random delay up to the maximum jitter
+    pub fn jitter(&self) -> Duration {
+        Duration::from_millis(fastrand::u64(0..=self.max_jitter.as_millis() as u64))
+    }
+}
+
+/// Controls the lease that lets only one execution environment apply a memory change
+#[derive(Clone, Debug)]
+pub struct CoordinationConfig {
+    /// DynamoDB table to keep the leases in, the coordination is disabled without it
+    pub table_name: Option<String>,
+    /// Custom DynamoDB endpoint, e.g. a local DynamoDB for testing
+    pub endpoint_url: Option<String>,
+    /// Time the other environments don't apply changes after one has taken the lease
+    pub lease_duration: Duration,
+}
+
+impl CoordinationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            table_name: env::var("OPTIMEIST_LEASE_TABLE_NAME").ok(),
+            endpoint_url: env::var("OPTIMEIST_DYNAMODB_ENDPOINT_URL").ok(),
+            lease_duration: Duration::from_secs(parse_env("OPTIMEIST_LEASE_DURATION_SECONDS", 300)),
+        }
+    }
+}
+
+/// Hard limits every memory change of the updater must respect
+#[derive(Clone, Debug)]
+pub struct GuardrailsConfig {
+    pub min_memory_mb: i32,
+    pub max_memory_mb: i32,
+    /// Memory size must be a multiple of the step
+    pub step_mb: i32,
+    /// Largest change per update relative to the current size, zero means no limit
+    pub max_change: f64,
+}
+
+impl GuardrailsConfig {
+    pub fn from_env() -> Self {
+        let lambda_range = LAMBDA_MIN_MEMORY_MB..=LAMBDA_MAX_MEMORY_MB;
+
+        let mut min_memory_mb = parse_env("OPTIMEIST_MIN_MEMORY_MB", LAMBDA_MIN_MEMORY_MB);
+        let mut max_memory_mb = parse_env("OPTIMEIST_MAX_MEMORY_MB", LAMBDA_MAX_MEMORY_MB);
+
+        if !lambda_range.contains(&min_memory_mb) || !lambda_range.contains(&max_memory_mb) {
+            warn!(
+                "Memory bounds {}-{} MB are clamped to the Lambda range {}-{} MB",
+                min_memory_mb, max_memory_mb, LAMBDA_MIN_MEMORY_MB, LAMBDA_MAX_MEMORY_MB
+            );
+            min_memory_mb = min_memory_mb.clamp(LAMBDA_MIN_MEMORY_MB, LAMBDA_MAX_MEMORY_MB);
+            max_memory_mb = max_memory_mb.clamp(LAMBDA_MIN_MEMORY_MB, LAMBDA_MAX_MEMORY_MB);
+        }
+
+        if min_memory_mb > max_memory_mb {
+            warn!(
+                "Minimum memory size {} MB is above the maximum {} MB, swapping them",
+                min_memory_mb, max_memory_mb
+            );
+            std::mem::swap(&mut min_memory_mb, &mut max_memory_mb);
+        }
+
+        Self {
+            min_memory_mb,
+            max_memory_mb,
+            step_mb: parse_env("OPTIMEIST_MEMORY_STEP_MB", 1).max(1),
+            max_change: parse_env("OPTIMEIST_MAX_MEMORY_CHANGE", 0.0_f64).max(0.0),
+        }
+    }
+}
+
+/// Defines where the memory size recommendations come from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DecisionMode {
+    /// Recommendations are requested from the backend
+    #[default]
+    Remote,
+    /// Recommendations are computed by the extension, the backend is not called at all
+    Local,
+}
+
+impl fmt::Display for DecisionMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecisionMode::Remote => write!(f, "REMOTE"),
+            DecisionMode::Local => write!(f, "LOCAL"),
+        }
+    }
+}
+
+impl FromStr for DecisionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "REMOTE" => Ok(DecisionMode::Remote),
+            "LOCAL" => Ok(DecisionMode::Local),
+            _ => Err(format!("Unknown decision mode: {s}")),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Default)]
+/// Defines what the updater does with the new memory sizes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpdateMode {
+    /// Memory size of the function is changed
+    #[default]
+    Apply,
+    /// Memory sizes are only logged and reported, the function is never changed
+    RecommendOnly,
+}
+
+impl fmt::Display for UpdateMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateMode::Apply => write!(f, "APPLY"),
+            UpdateMode::RecommendOnly => write!(f, "RECOMMEND_ONLY"),
+        }
+    }
+}
+
+impl FromStr for UpdateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "APPLY" => Ok(UpdateMode::Apply),
+            "RECOMMEND_ONLY" => Ok(UpdateMode::RecommendOnly),
+            _ => Err(format!("Unknown update mode: {s}")),
+        }
+    }
+}
+
+impl UpdateMode {
+    /// Reads the mode from the env variable. An invalid value most likely means
+    /// the recommend-only mode was intended, so it's used to never change the function by mistake.
+    fn from_env() -> Self {
+        let Ok(value) = env::var("OPTIMEIST_UPDATE_MODE") else {
+            return UpdateMode::default();
+        };
+
+        value.parse().unwrap_or_else(|e| {
+            error!(
+                "Invalid OPTIMEIST_UPDATE_MODE: {}. Falling back to {}",
+                e,
+                UpdateMode::RecommendOnly
+            );
+            UpdateMode::RecommendOnly
+        })
+    }
+}
+
+/// Controls the local recommender used in the local decision mode
+#[derive(Clone, Debug)]
+pub struct LocalConfig {
+    /// Number of invocations with a memory size required to judge it
+    pub min_samples: u64,
+    /// SSM parameter to keep the statistics of the function in without the lease table
+    pub stats_parameter_name: String,
+}
+
+impl LocalConfig {
+    pub fn from_env(function_name: &str) -> Self {
+        Self {
+            min_samples: parse_env("OPTIMEIST_LOCAL_MIN_SAMPLES", 50).max(1),
+            stats_parameter_name: parse_env(
+                "OPTIMEIST_STATS_PARAMETER_NAME",
+                format!("/optimeist/{function_name}/stats"),
+            ),
+        }
+    }
+}
+
+/// Controls the emergency upsizing after an out-of-memory invocation
+#[derive(Clone, Debug)]
+pub struct OomConfig {
+    /// Memory size is multiplied by the factor, values not greater than 1 disable upsizing
+    pub factor: f64,
+    /// Upsizing never goes above this memory size
+    pub max_memory_mb: i32,
+}
+
+impl OomConfig {
+    pub fn from_env() -> Self {
+        Self {
+            factor: parse_env("OPTIMEIST_OOM_UPSIZE_FACTOR", 1.5),
+            max_memory_mb: parse_env("OPTIMEIST_OOM_MAX_MEMORY_MB", 10240),
+        }
+    }
+}
+
+/// Controls how the backend API is called
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+    /// Backend URL without a trailing slash
+    pub base_url: String,
+    pub retry: RetryPolicy,
+}
+
+impl ApiConfig {
+    pub fn from_env() -> Self {
+        Self {
+            base_url: api_url_from_env(),
+            retry: RetryPolicy {
+                max_attempts: parse_env("OPTIMEIST_API_MAX_ATTEMPTS", 3).max(1),
+                base_delay: Duration::from_millis(parse_env(
+                    "OPTIMEIST_API_RETRY_BASE_DELAY_MS",
+                    200,
+                )),
+                max_delay: Duration::from_millis(parse_env(
+                    "OPTIMEIST_API_RETRY_MAX_DELAY_MS",
+                    5_000,
+                )),
+            },
+        }
+    }
+}
+
+/// Controls how telemetry batches are uploaded to the backend
+#[derive(Clone, Debug)]
+pub struct TelemetryConfig {
+    /// Maximum number of metrics records sent in a single request
+    pub chunk_size: usize,
+    /// Maximum number of requests in flight at the same time
+    pub max_concurrency: usize,
+    /// Directory to keep metrics that failed to upload
+    pub spool_dir: PathBuf,
+    /// Size cap of the spool directory, zero disables spooling
+    pub spool_max_bytes: u64,
+    /// Defines which batches are dropped when the spool is full
+    pub spool_eviction: SpoolEviction,
+    /// Maximum number of batches waiting for the upload
+    pub queue_capacity: usize,
+    /// Defines which batches are dropped when the upload queue is full
+    pub queue_overflow: OverflowPolicy,
+    /// Destinations of the telemetry records
+    pub sinks: Vec<SinkKind>,
+    /// File the records are appended to by the file sink
+    pub file_path: PathBuf,
+    /// CloudWatch namespace of the metrics written by the EMF sink
+    pub emf_namespace: String,
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        Self {
+            chunk_size: parse_env("OPTIMEIST_TELEMETRY_CHUNK_SIZE", 100).max(1),
+            max_concurrency: parse_env("OPTIMEIST_TELEMETRY_MAX_CONCURRENCY", 4).max(1),
+            spool_dir: parse_env("OPTIMEIST_SPOOL_DIR", PathBuf::from("/tmp/optimeist-spool")),
+            spool_max_bytes: parse_env("OPTIMEIST_SPOOL_MAX_BYTES", 10 * 1024 * 1024),
+            spool_eviction: parse_env("OPTIMEIST_SPOOL_EVICTION", SpoolEviction::default()),
+            queue_capacity: parse_env("OPTIMEIST_UPLOAD_QUEUE_CAPACITY", 32).max(1),
+            queue_overflow: parse_env("OPTIMEIST_UPLOAD_QUEUE_OVERFLOW", OverflowPolicy::default()),
+            sinks: sinks_from_env(),
+            file_path: parse_env(
+                "OPTIMEIST_TELEMETRY_FILE",
+                PathBuf::from("/tmp/optimeist-telemetry.jsonl"),
+            ),
+            emf_namespace: parse_env("OPTIMEIST_EMF_NAMESPACE", "Optimeist".to_string()),
+        }
+    }
+}
+
+/// Reads the comma-separated sinks from the env variable, the backend is the default one.
+/// Unknown sinks are skipped.
+fn sinks_from_env() -> Vec<SinkKind> {
+    let Ok(value) = env::var("OPTIMEIST_TELEMETRY_SINKS") else {
+        return vec![SinkKind::Backend];
+    };
+
+    let mut sinks = vec![];
+
+    for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+        match name.parse() {
+            Ok(sink) if !sinks.contains(&sink) => sinks.push(sink),
+            Ok(_) => {}
+            Err(e) => warn!("Invalid OPTIMEIST_TELEMETRY_SINKS: {}", e),
+        }
+    }
+
+    sinks
+}
+
+/// Weight of the cost in the BALANCED strategy when it's not given explicitly
+pub const DEFAULT_BALANCED_WEIGHT: f64 = 0.5;
+
+/// Defines what the memory size is optimized for.
+/// Written as `COST`, `SPEED`, `BALANCED` or `BALANCED#<weight>`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Strategy {
     Cost,
     Speed,
-    #[default]
-    Balanced,
+    /// Mix of cost and speed, the weight from 0 to 1 is the share of the cost:
+    /// 1 is the same as COST and 0 is the same as SPEED
+    Balanced(f64),
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Balanced(DEFAULT_BALANCED_WEIGHT)
+    }
 }
 
 impl fmt::Display for Strategy {
@@ -40,8 +647,11 @@ impl fmt::Display for Strategy {
         match self {
             Strategy::Cost => write!(f, "COST"),
             Strategy::Speed => write!(f, "SPEED"),
-            // FIXME Add weight to BALANCED strategy like BALANCED#0.5
-            Strategy::Balanced => write!(f, "BALANCED"),
+            // Keep the plain name for the default weight, the backend knows it this way
+            Strategy::Balanced(weight) if *weight == DEFAULT_BALANCED_WEIGHT => {
+                write!(f, "BALANCED")
+            }
+            Strategy::Balanced(weight) => write!(f, "BALANCED#{weight}"),
         }
     }
 }
@@ -50,40 +660,63 @@ impl FromStr for Strategy {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_uppercase().as_str() {
+        let value = s.trim().to_uppercase();
+
+        if let Some(weight) = value.strip_prefix("BALANCED#") {
+            return match weight.parse::<f64>() {
+                Ok(weight) if (0.0..=1.0).contains(&weight) => Ok(Strategy::Balanced(weight)),
+                _ => Err(format!(
+                    "Invalid BALANCED weight {weight:?}, expected a number from 0 to 1"
+                )),
+            };
+        }
+
+        match value.as_str() {
             "COST" => Ok(Strategy::Cost),
             "SPEED" => Ok(Strategy::Speed),
-            // FIXME Add weight to BALANCED strategy like BALANCED#0.5
-            "BALANCED" => Ok(Strategy::Balanced),
-            _ => Ok(Strategy::Balanced), // Using Balanced as default
+            "BALANCED" => Ok(Strategy::default()),
+            _ => Err(format!(
+                "Unknown strategy {s:?}, expected COST, SPEED, BALANCED or BALANCED#<0..1>"
+            )),
         }
     }
 }
 
-// This will allow conversion from any type that can be converted to &str
-impl<T: AsRef<str>> From<T> for Strategy {
-    fn from(s: T) -> Self {
-        Strategy::from_str(s.as_ref()).unwrap_or_default()
+// Serialized the same way it's sent in the query string
+impl Serialize for Strategy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
-impl LambdaEnvironment {
-    pub async fn new(config: &SdkConfig) -> Result<Self> {
-        let access_token_secret_arn = env::var("OPTIMEIST_ACCESS_TOKEN_SECRET_ARN")
-            .wrap_err("Failed to get OPTIMEIST_ACCESS_TOKEN_SECRET_ARN env variable")?;
+impl Strategy {
+    /// Reads the strategy from the env variable, an invalid value is reported and replaced
+    /// with the default one, so a typo doesn't break the function
+    fn from_env() -> Self {
+        let Ok(value) = env::var("OPTIMEIST_DECISION_ALGORITHM_TYPE") else {
+            return Strategy::default();
+        };
 
-        let secret_client = SecretsClient::new(config);
+        value.parse().unwrap_or_else(|e| {
+            error!(
+                "Invalid OPTIMEIST_DECISION_ALGORITHM_TYPE: {}. Falling back to {}",
+                e,
+                Strategy::default()
+            );
+            Strategy::default()
+        })
+    }
+}
 
-        let response = secret_client
-            .get_secret_value()
-            .secret_id(access_token_secret_arn.clone())
-            .send()
-            .await
-            .wrap_err("Failed to get access token secret value")?;
+impl LambdaEnvironment {
+    pub async fn new(config: &SdkConfig) -> Result<Self> {
+        let decision_mode = parse_env("OPTIMEIST_DECISION_MODE", DecisionMode::default());
 
-        let access_token = response
-            .secret_string
-            .ok_or_eyre("Failed to get a secret string")?;
+        // The backend is not called in the local mode, so no access token is needed
+        let access_token = match decision_mode {
+            DecisionMode::Remote => get_access_token(config).await?,
+            DecisionMode::Local => String::new(),
+        };
 
         let function_name = env::var("AWS_LAMBDA_FUNCTION_NAME")?;
         let lambda_client = LambdaClient::new(config);
@@ -102,14 +735,130 @@ impl LambdaEnvironment {
                 .ok_or_eyre("Failed to get function ARN")?,
 
             access_token,
-            strategy: Strategy::from(
-                env::var("OPTIMEIST_DECISION_ALGORITHM_TYPE").unwrap_or("balanced".to_string()),
-            ),
+            strategy: Strategy::from_env(),
+            decision_mode,
+            update_mode: UpdateMode::from_env(),
+            local: LocalConfig::from_env(&function_name),
+            canary: CanaryConfig::from_env(),
+            rollback: RollbackConfig::from_env(),
+            audit: AuditConfig::from_env(&function_name),
+            throttle: ThrottleConfig::from_env(&function_name),
+            otlp: OtlpConfig::from_env(),
             region: env::var("AWS_REGION")?,
             version: env::var("AWS_LAMBDA_FUNCTION_VERSION")?,
             name: function_name,
             memory_size_mb: env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE")?.parse()?,
             memory_parameter_name: env::var("OPTIMEIST_MEMORY_PARAMETER_NAME").ok(),
+            publish_alias: env::var("OPTIMEIST_PUBLISH_ALIAS").ok(),
+            telemetry: TelemetryConfig::from_env(),
+            api: ApiConfig::from_env(),
+            oom: OomConfig::from_env(),
+            guardrails: GuardrailsConfig::from_env(),
+            coordination: CoordinationConfig::from_env(),
+            poll: PollConfig::from_env(),
+            environment_id: format!("{:016x}", fastrand::u64(..)),
         })
     }
 }
+
+/// Reads the backend URL from the env variable, falling back to the built-in one
+/// if it's missing or isn't a valid HTTP(S) URL
+fn api_url_from_env() -> String {
+    let Ok(value) = env::var("OPTIMEIST_API_URL") else {
+        return DEFAULT_API_URL.trim_end_matches('/').to_string();
+    };
+
+    match reqwest::Url::parse(value.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.query().is_none() => {
+            url.as_str().trim_end_matches('/').to_string()
+        }
+        _ => {
+            error!(
+                "Invalid OPTIMEIST_API_URL: {:?}, expected an HTTP(S) URL without a query. Falling back to {}",
+                value, DEFAULT_API_URL
+            );
+            DEFAULT_API_URL.trim_end_matches('/').to_string()
+        }
+    }
+}
+
+/// Reads the backend access token from the secret referenced by the env variable
+async fn get_access_token(config: &SdkConfig) -> Result<String> {
+    let access_token_secret_arn = env::var("OPTIMEIST_ACCESS_TOKEN_SECRET_ARN")
+        .wrap_err("Failed to get OPTIMEIST_ACCESS_TOKEN_SECRET_ARN env variable")?;
+
+    let secret_client = SecretsClient::new(config);
+
+    let response = secret_client
+        .get_secret_value()
+        .secret_id(access_token_secret_arn)
+        .send()
+        .await
+        .wrap_err("Failed to get access token secret value")?;
+
+    response
+        .secret_string
+        .ok_or_eyre("Failed to get a secret string")
+}
+
+/// Reads an optional env variable, falling back to the default if it's missing or invalid
+pub fn parse_env<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Invalid value of {}: {:?}, using the default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+impl LambdaEnvironment {
+    /// Environment of a function named `test` with the default configuration
+    pub fn for_test() -> Self {
+        let function_name = "test";
+
+        Self {
+            arn: format!("arn:aws:lambda:us-east-1:123456789012:function:{function_name}"),
+            region: "us-east-1".to_string(),
+            version: "$LATEST".to_string(),
+            name: function_name.to_string(),
+            memory_size_mb: 512,
+            strategy: Strategy::default(),
+            decision_mode: DecisionMode::default(),
+            update_mode: UpdateMode::default(),
+            environment_id: "0123456789abcdef".to_string(),
+            memory_parameter_name: None,
+            publish_alias: None,
+            access_token: String::new(),
+            telemetry: TelemetryConfig::from_env(),
+            api: ApiConfig::from_env(),
+            oom: OomConfig::from_env(),
+            local: LocalConfig::from_env(function_name),
+            guardrails: GuardrailsConfig::from_env(),
+            coordination: CoordinationConfig::from_env(),
+            poll: PollConfig::from_env(),
+            canary: CanaryConfig::from_env(),
+            rollback: RollbackConfig::from_env(),
+            audit: AuditConfig::from_env(function_name),
+            throttle: ThrottleConfig::from_env(function_name),
+            otlp: OtlpConfig::from_env(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_percent_encoded_otlp_headers() {
+        assert_eq!(
+            parse_otlp_headers("api-key=a%20b%3Dc, x-tenant = one ,invalid,"),
+            vec![
+                ("api-key".to_string(), "a b=c".to_string()),
+                ("x-tenant".to_string(), "one".to_string()),
+            ]
+        );
+    }
+}
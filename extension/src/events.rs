@@ -1,21 +1,54 @@
-use crate::environment::LambdaEnvironment;
+use crate::audit::{AuditLog, AuditRecord};
+use crate::backend::{BackendClient, BackendError, RetryPolicy};
+use crate::canary;
+use crate::canary::{ActiveCanary, CanaryStore};
+use crate::coordination::{Coordinator, Lease};
+use crate::emf;
+use crate::environment::{DecisionMode, LambdaEnvironment, UpdateMode};
+use crate::guardrails::guard_memory_size;
+use crate::oom::{is_out_of_memory, upsized_memory};
+use crate::otlp::OtlpExporter;
+use crate::recommender::LocalRecommender;
+use crate::rollback;
+use crate::rollback::{Change, Observation, Pin, RollbackStore, RollbackTracker};
+use crate::sink::SinkKind;
+use crate::stats::{FunctionStats, StatsStore};
+use crate::telemetry::{shutdown_telemetry, Metrics, TelemetryContext};
+use crate::throttle;
+use crate::throttle::{ChangeStore, Stability, Suppression};
 use aws_config::SdkConfig;
+use aws_sdk_lambda::types::{FunctionConfiguration, LastUpdateStatus, State};
 use aws_sdk_lambda::Client as LambdaClient;
 use aws_sdk_ssm::Client as SsmClient;
-use eyre::eyre;
+use eyre::{eyre, OptionExt};
 use lambda_extension::{LambdaEvent, NextEvent};
-use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::oneshot;
 use tokio::sync::oneshot::{Receiver, Sender};
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
-use tokio::time::interval;
-use tracing::{error, info};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
-// API_URL contains the backend URL without a trailing slash
-const BASE_API_URL: &str = env!("METRICS_API_URL");
+/// Version of the function that is changed by the configuration updates
+const LATEST_VERSION: &str = "$LATEST";
+
+/// Repeats the configuration update conflicting with another update of the function
+const UPDATE_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    base_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(10),
+};
+
+/// How often the function is checked while an update is in progress
+const UPDATE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Number of checks before giving up on an update of the function, about a minute
+const UPDATE_MAX_POLLS: u32 = 30;
+
+/// Time left for the extension to respond or exit before the deadline of an event
+const DEADLINE_MARGIN: Duration = Duration::from_millis(100);
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,98 +57,1236 @@ pub struct LambdaConfig {
     pub memory_size_mb: Option<i32>,
 }
 
-/// Handles the Shutdown Lambda event and gracefully shut down the updater task
-pub(crate) async fn events_handler(updater: Updater, event: LambdaEvent) -> eyre::Result<()> {
-    if let NextEvent::Shutdown(_) = event.next {
-        info!("Extension is shutting down");
-        updater.shutdown().await?;
+/// Action taken by the updater on its own, reported to the backend
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpdaterEvent {
+    /// Memory size was changed to the recommended one
+    #[serde(rename_all = "camelCase")]
+    MemoryUpdate {
+        #[serde(rename = "previousMemorySizeMB")]
+        previous_memory_size_mb: i32,
+        #[serde(rename = "memorySizeMB")]
+        memory_size_mb: i32,
+        applied: bool,
+        /// The new size got a share of the alias traffic, not all of it
+        canary: bool,
+        /// Version published with the change in the publish mode
+        #[serde(skip_serializing_if = "Option::is_none")]
+        published_version: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// Memory change was reverted after a regression
+    #[serde(rename_all = "camelCase")]
+    Rollback {
+        #[serde(rename = "previousMemorySizeMB")]
+        previous_memory_size_mb: i32,
+        #[serde(rename = "memorySizeMB")]
+        memory_size_mb: i32,
+        reason: String,
+        /// Unix timestamp in seconds until the memory size is kept
+        pinned_until: i64,
+        applied: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// Canary rollout of a memory size completed
+    #[serde(rename_all = "camelCase")]
+    CanaryResult {
+        stable_version: String,
+        #[serde(rename = "stableMemorySizeMB")]
+        stable_memory_size_mb: i32,
+        canary_version: String,
+        #[serde(rename = "canaryMemorySizeMB")]
+        canary_memory_size_mb: i32,
+        promoted: bool,
+        /// Why the canary is reverted
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// Memory size the updater would switch to, reported instead of applying it
+    /// in the recommend-only mode
+    #[serde(rename_all = "camelCase")]
+    Recommendation {
+        #[serde(rename = "currentMemorySizeMB")]
+        current_memory_size_mb: i32,
+        #[serde(rename = "memorySizeMB")]
+        memory_size_mb: i32,
+        reason: RecommendationReason,
+    },
+
+    /// Recommended memory size was not applied because of the cooldown,
+    /// the daily change budget or an unstable recommendation
+    #[serde(rename_all = "camelCase")]
+    SuppressedUpdate {
+        #[serde(rename = "currentMemorySizeMB")]
+        current_memory_size_mb: i32,
+        #[serde(rename = "memorySizeMB")]
+        memory_size_mb: i32,
+        reason: String,
+    },
+
+    /// Memory was raised after an invocation ran out of memory
+    #[serde(rename_all = "camelCase")]
+    OutOfMemory {
+        request_id: String,
+        #[serde(rename = "previousMemorySizeMB")]
+        previous_memory_size_mb: i32,
+        #[serde(rename = "memorySizeMB")]
+        memory_size_mb: i32,
+        applied: bool,
+        /// Version published with the change in the publish mode
+        #[serde(skip_serializing_if = "Option::is_none")]
+        published_version: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// What made the updater come up with a new memory size
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RecommendationReason {
+    /// Regular poll of the backend or the local recommender
+    Poll,
+    /// Emergency upsizing after an out-of-memory invocation
+    OutOfMemory,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventData<'a> {
+    event: &'a UpdaterEvent,
+    meta: &'a LambdaEnvironment,
+    /// Timestamp in microseconds when the event happened
+    timestamp_us: String,
+}
+
+/// Handles the Lambda events: an Invoke event lets the updater poll if it's time and waits
+/// for the poll within the poll budget, the Shutdown event gracefully shuts down
+/// the updater task and uploads the pending telemetry within the shutdown deadline
+pub(crate) async fn events_handler(
+    updater: Updater,
+    telemetry: TelemetryContext,
+    event: LambdaEvent,
+) -> eyre::Result<()> {
+    match event.next {
+        NextEvent::Invoke(event) => {
+            // Waiting delays the end of the invocation, so only the short poll is waited for,
+            // bounded by the budget. Applying a change continues in the background: if the
+            // sandbox is frozen midway, it resumes alongside the next invocation.
+            let deadline =
+                event_deadline(event.deadline_ms).min(Instant::now() + updater.poll_budget);
+
+            if tokio::time::timeout_at(deadline, updater.notify_invoke())
+                .await
+                .is_err()
+            {
+                info!("Updater poll continues in the background after the poll budget");
+            }
+        }
+        NextEvent::Shutdown(event) => {
+            info!("Extension is shutting down: {}", event.shutdown_reason);
+            let deadline = event_deadline(event.deadline_ms);
+
+            let (updater_result, _) = tokio::join!(
+                tokio::time::timeout_at(deadline, updater.shutdown()),
+                shutdown_telemetry(&telemetry, deadline),
+            );
+
+            match updater_result {
+                Ok(result) => result?,
+                Err(_) => warn!("Updater task did not stop before the shutdown deadline"),
+            }
+        }
     }
     Ok(())
 }
 
-/// Periodically updates the Lambda function's memory size based on the provided API
-/// Util the shutdown signal is received.
+/// Converts the Unix time of the event deadline in milliseconds into an instant,
+/// leaving a margin for the extension to respond
+fn event_deadline(deadline_ms: u64) -> Instant {
+    let now_ms = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    let remaining = Duration::from_millis(deadline_ms.saturating_sub(now_ms));
+
+    Instant::now() + remaining.saturating_sub(DEADLINE_MARGIN)
+}
+
+/// Updates the Lambda function's memory size based on the provided API
+/// until the shutdown signal is received.
+///
+/// Timers are unreliable in a sandbox frozen between invocations, so polling is driven
+/// by the Invoke events: the first event after the poll interval has passed triggers a poll.
+/// The events handler waits for the poll decision within a short budget. Applying the change
+/// is not waited for, it runs alongside the following invocations instead of delaying them.
 async fn updater_task(
     // Shutdown signal
     mut shutdown_rx: Receiver<()>,
     // Lambda environment variables
     environment: LambdaEnvironment,
 
+    // Client for the backend API
+    backend: BackendClient,
+
     // AWS clients required for update the Lambda function
     clients: AwsClients,
+
+    // Metrics of the invocations received by the telemetry handler
+    mut observations: mpsc::Receiver<Vec<Metrics>>,
+
+    // Signals of the Invoke events, the sender is dropped once the event is handled
+    mut invokes: mpsc::Receiver<oneshot::Sender<()>>,
 ) {
-    let client = Client::new();
     let lambda_environment = environment.clone();
 
-    // Use the current memory size from env as a fallback
-    let fallback_memory_size = lambda_environment.memory_size_mb;
+    // The memory size this execution environment knows about, starting with the one from env
+    let mut current_memory_size = lambda_environment.memory_size_mb;
+
+    // In the local mode the memory size is computed from the received metrics
+    let mut recommender = match lambda_environment.decision_mode {
+        DecisionMode::Local => Some(LocalRecommender::new(
+            lambda_environment.local.clone(),
+            match &clients.coordinator {
+                Some(coordinator) => StatsStore::Shared(coordinator.shared("stats")),
+                None => StatsStore::Ssm {
+                    client: clients.ssm_client.clone(),
+                    parameter_name: lambda_environment.local.stats_parameter_name.clone(),
+                },
+            },
+        )),
+        DecisionMode::Remote => None,
+    };
+
+    // Changes of $LATEST don't reach the traffic coming to a published version
+    if lambda_environment.version != LATEST_VERSION && lambda_environment.publish_alias.is_none() {
+        warn!(
+            "Running version {}, memory changes are applied to {} only. \
+            Set OPTIMEIST_PUBLISH_ALIAS to publish a version and move the alias to it",
+            lambda_environment.version, LATEST_VERSION
+        );
+    }
+
+    if lambda_environment.canary.is_enabled() {
+        if lambda_environment.publish_alias.is_none() {
+            warn!(
+                "OPTIMEIST_CANARY_WEIGHT is set without OPTIMEIST_PUBLISH_ALIAS, \
+                the canary rollout is disabled"
+            );
+        } else if clients.canary.is_none() {
+            warn!("Canary rollouts need the lease table, set OPTIMEIST_LEASE_TABLE_NAME to enable them");
+        }
+    }
+
+    // Applied changes are observed for regressions and reverted if needed
+    // Nothing is changed in the recommend-only mode, so nothing is reverted either
+    let rollback_enabled = lambda_environment.rollback.is_enabled()
+        && lambda_environment.update_mode != UpdateMode::RecommendOnly;
+
+    let mut rollback = match &clients.coordinator {
+        Some(coordinator) if rollback_enabled => {
+            Some(RollbackTracker::new(RollbackStore::new(coordinator)))
+        }
+        None if rollback_enabled => {
+            warn!("Rollbacks need the lease table, set OPTIMEIST_LEASE_TABLE_NAME to enable them");
+            None
+        }
+        _ => None,
+    };
+
+    // Consecutive polls recommending the same memory size
+    let mut stability = Stability::default();
+
+    // This environment's invocations not yet added to the stats of the canary rollout
+    let mut canary_pending = FunctionStats::default();
 
-    // Polling API every 5 minutes to get a new memory size
-    let mut interval = interval(Duration::from_secs(60 * 5));
+    // The first poll is spread by the jitter as well, so environments started together
+    // don't poll together
+    let mut next_poll_at = Instant::now() + lambda_environment.poll.jitter();
 
     loop {
         tokio::select! {
             _ = &mut shutdown_rx => break,
-            _ = interval.tick() => {
-                let api_url = format!("{BASE_API_URL}/config");
-                info!("Requesting new RAM size from the provider: {}", api_url);
-
-                let ram_size = match client.get(api_url)
-                    .header("Authorization", format!("Bearer {}", lambda_environment.access_token))
-                    .timeout(Duration::from_secs(10))
-                    .query(&[
-                        ("name", lambda_environment.name.clone()),
-                        ("region", lambda_environment.region.clone()),
-                        ("version", lambda_environment.version.clone()),
-                        ("strategy", lambda_environment.strategy.to_string()),
-                        ("arn", lambda_environment.arn.clone()),
-                    ])
-                    .send()
-                    .await {
-                        Ok(response) => {
-                            match response.json::<LambdaConfig>().await {
-                                Ok(config) => config.memory_size_mb.unwrap_or(fallback_memory_size),
-                                Err(e) => {
-                                    error!("Failed to parse response: {:?}", e);
-                                    fallback_memory_size
-                                }
-                            }
-                        },
+            Some(metrics) = observations.recv() => {
+                if let Some(recommender) = recommender.as_mut() {
+                    recommender.observe(&metrics);
+                }
+
+                if lambda_environment.canary.is_enabled() {
+                    canary_pending.observe(&metrics);
+                }
+
+                if let Some(rollback) = rollback.as_mut() {
+                    rollback.collector.observe(&metrics);
+                }
+
+                // Only the invocations with the current memory size matter,
+                // the older ones were already handled
+                let oom = metrics.iter().find(|metrics| {
+                    metrics.memory_size_mb == current_memory_size as u64 && is_out_of_memory(metrics)
+                });
+
+                // A change under observation is reverted instead of raising the memory further
+                let is_watched = rollback
+                    .as_ref()
+                    .is_some_and(|rollback| rollback.watching == Some(current_memory_size));
+
+                if let Some(rollback) = rollback.as_mut().filter(|_| oom.is_some() && is_watched) {
+                    let is_blocked = check_rollback(
+                        &lambda_environment,
+                        &backend,
+                        &clients,
+                        rollback,
+                        &mut current_memory_size,
+                    )
+                    .await;
+
+                    // Raised as usual if there was nothing to revert, e.g. the change was
+                    // confirmed in the meantime
+                    if is_blocked {
+                        continue;
+                    }
+                }
+
+                if let Some(oom) = oom {
+                    current_memory_size = handle_out_of_memory(
+                        &lambda_environment,
+                        &backend,
+                        &clients,
+                        &oom.request_id,
+                        current_memory_size,
+                    ).await;
+                }
+            }
+            Some(done) = invokes.recv() => {
+                let now = Instant::now();
+
+                if now < next_poll_at {
+                    continue;
+                }
+
+                // Polls missed while the sandbox was frozen are skipped, not caught up on
+                let poll = &lambda_environment.poll;
+                next_poll_at = now + poll.interval + poll.jitter();
+
+                // No new changes are made until the canary rollout in progress completes
+                if let Some((alias, store)) = canary_rollout(&lambda_environment, &clients) {
+                    let active = store.observe(&canary_pending).await;
 
+                    match active {
+                        Ok(Some((active, stats))) => {
+                            canary_pending = FunctionStats::default();
+                            drop(done);
+                            handle_canary(
+                                &lambda_environment,
+                                &backend,
+                                &clients,
+                                (alias, store),
+                                &active,
+                                &stats,
+                            )
+                            .await;
+                            continue;
+                        }
+                        // Stats are collected only while a canary is in progress
+                        Ok(None) => canary_pending = FunctionStats::default(),
                         Err(e) => {
-                            error!("Failed to get a new RAM size: {:?}", e);
-                            fallback_memory_size
+                            error!("Failed to check the canary rollout: {:?}", e);
+                            continue;
                         }
-                    };
+                    }
+                }
+
+                // No new changes are made while the last one is observed or after a rollback
+                if let Some(rollback) = rollback.as_mut() {
+                    let is_blocked = check_rollback(
+                        &lambda_environment,
+                        &backend,
+                        &clients,
+                        rollback,
+                        &mut current_memory_size,
+                    )
+                    .await;
+
+                    if is_blocked {
+                        continue;
+                    }
+                }
 
-                if ram_size == fallback_memory_size {
-                    info!("No new RAM config is available: {}", fallback_memory_size);
+                let ram_size = match recommender.as_mut() {
+                    Some(recommender) => {
+                        recommender
+                            .recommend(current_memory_size, &lambda_environment.strategy)
+                            .await
+                    }
+                    None => {
+                        request_memory_size(&lambda_environment, &backend, current_memory_size)
+                            .await
+                    }
+                };
+
+                if ram_size == current_memory_size {
+                    info!("No new RAM config is available: {}", current_memory_size);
+                    stability.reset();
                     continue;
                 }
 
                 info!("Received a new RAM size: {}", ram_size);
 
-                let lambda = update_lambda_config(&clients.lambda_client, &lambda_environment.name, ram_size);
-                let ssm = update_ssm_parameter(&clients.ssm_client, lambda_environment.memory_parameter_name.as_deref(), ram_size);
-
-                let (lambda_result, ssm_result) = tokio::join!(
-                    lambda,
-                    ssm,
+                let guarded = guard_memory_size(
+                    &lambda_environment.guardrails,
+                    current_memory_size,
+                    ram_size,
                 );
 
-                match (lambda_result, ssm_result) {
-                    (Ok(_), Ok(_)) => info!("Lambda and SSM parameters updated successfully"),
-                    (Err(e1), Ok(_)) => error!("Failed to update Lambda: {:?}", e1),
-                    (Ok(_), Err(e2)) => error!("Failed to update SSM: {:?}", e2),
-                    (Err(e1), Err(e2)) => error!("Failed to update Lambda and SSM: {:?} and {:?}", e1, e2),
+                let ram_size = match guarded {
+                    Ok(ram_size) => ram_size,
+                    Err(reason) => {
+                        warn!("Rejected the RAM size {}: {}", ram_size, reason);
+                        stability.reset();
+                        continue;
+                    }
+                };
+
+                if lambda_environment.update_mode == UpdateMode::RecommendOnly {
+                    report_recommendation(
+                        &lambda_environment,
+                        &backend,
+                        &clients,
+                        current_memory_size,
+                        ram_size,
+                        RecommendationReason::Poll,
+                    )
+                    .await;
+                    continue;
+                }
+
+                // The decision is made, applying it doesn't hold the invocation
+                drop(done);
+
+                let polls = stability.observe(ram_size);
+                let throttled = check_throttle(&lambda_environment, &clients, polls).await;
+
+                if let Err(suppression) = throttled {
+                    report_suppressed(
+                        &lambda_environment,
+                        &backend,
+                        &clients,
+                        current_memory_size,
+                        ram_size,
+                        suppression,
+                    )
+                    .await;
+                    continue;
+                }
+
+                stability.reset();
+
+                let result =
+                    apply_memory_size(&clients, &lambda_environment, ram_size, Rollout::Canary)
+                        .await;
+
+                // The environment holding the lease reports the change
+                if let Ok(ApplyOutcome::Skipped { .. }) = result {
+                    continue;
+                }
+
+                let canary = canary_rollout(&lambda_environment, &clients).is_some();
+
+                let event = UpdaterEvent::MemoryUpdate {
+                    previous_memory_size_mb: current_memory_size,
+                    memory_size_mb: ram_size,
+                    applied: result.is_ok(),
+                    canary,
+                    published_version: result
+                        .as_ref()
+                        .ok()
+                        .and_then(ApplyOutcome::published_version),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                };
+
+                report_change(&clients, &backend, &lambda_environment, &event).await;
+
+                // The canary version runs in other environments, this one keeps its memory size
+                if result.is_ok() && !canary {
+                    if let Some(rollback) = rollback.as_mut() {
+                        observe_change(rollback, current_memory_size, ram_size).await;
+                    }
+
+                    current_memory_size = ram_size;
                 }
             }
         }
     }
 
+    if let Some(recommender) = recommender.as_mut() {
+        recommender.flush().await;
+    }
+
     info!("Updater completed successfully");
 }
 
+/// Requests the memory size from the backend.
+/// Returns the current memory size if the backend has no recommendation or fails.
+async fn request_memory_size(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    memory_size: i32,
+) -> i32 {
+    info!(
+        "Requesting new RAM size from the provider: {}",
+        backend.url("/config")
+    );
+
+    let response = backend
+        .get::<LambdaConfig>(
+            "/config",
+            &[
+                ("name", environment.name.clone()),
+                ("region", environment.region.clone()),
+                ("version", environment.version.clone()),
+                ("strategy", environment.strategy.to_string()),
+                ("arn", environment.arn.clone()),
+            ],
+        )
+        .await;
+
+    match response {
+        Ok(config) => config.memory_size_mb.unwrap_or(memory_size),
+
+        Err(e @ BackendError::Auth(_)) => {
+            error!(
+                "Failed to get a new RAM size, check the access token: {}",
+                e
+            );
+            memory_size
+        }
+
+        Err(e @ BackendError::Retryable { .. }) => {
+            warn!(
+                "Failed to get a new RAM size, will retry on the next poll: {}",
+                e
+            );
+            memory_size
+        }
+
+        Err(e) => {
+            error!("Failed to get a new RAM size: {}", e);
+            memory_size
+        }
+    }
+}
+
+/// Raises the memory size right away after an out-of-memory invocation.
+/// Returns the memory size the function is configured with after that.
+async fn handle_out_of_memory(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    clients: &AwsClients,
+    request_id: &str,
+    memory_size: i32,
+) -> i32 {
+    warn!(
+        "Invocation {} ran out of memory with {} MB",
+        request_id, memory_size
+    );
+
+    let upsized = upsized_memory(&environment.oom, memory_size)
+        .ok_or_else(|| "Upsizing is disabled or the ceiling is reached".to_string())
+        .and_then(|ram_size| guard_memory_size(&environment.guardrails, memory_size, ram_size));
+
+    let ram_size = match upsized {
+        Ok(ram_size) => ram_size,
+        Err(reason) => {
+            warn!(
+                "Memory can't be raised above {} MB: {}",
+                memory_size, reason
+            );
+
+            let event = UpdaterEvent::OutOfMemory {
+                request_id: request_id.to_string(),
+                previous_memory_size_mb: memory_size,
+                memory_size_mb: memory_size,
+                applied: false,
+                published_version: None,
+                error: Some(reason),
+            };
+
+            report_change(clients, backend, environment, &event).await;
+            return memory_size;
+        }
+    };
+
+    if environment.update_mode == UpdateMode::RecommendOnly {
+        report_recommendation(
+            environment,
+            backend,
+            clients,
+            memory_size,
+            ram_size,
+            RecommendationReason::OutOfMemory,
+        )
+        .await;
+        return memory_size;
+    }
+
+    info!("Raising the memory size to {} MB", ram_size);
+    let result = apply_memory_size(clients, environment, ram_size, Rollout::Full).await;
+
+    // Reported as not applied, the environment holding the lease reports its own change
+    let error = match &result {
+        Ok(ApplyOutcome::Skipped { memory_size_mb }) => Some(format!(
+            "Skipped, another environment is changing the memory size to {memory_size_mb} MB"
+        )),
+        Ok(ApplyOutcome::Applied { .. }) => None,
+        Err(e) => Some(e.to_string()),
+    };
+
+    let event = UpdaterEvent::OutOfMemory {
+        request_id: request_id.to_string(),
+        previous_memory_size_mb: memory_size,
+        memory_size_mb: ram_size,
+        applied: error.is_none(),
+        published_version: result
+            .as_ref()
+            .ok()
+            .and_then(ApplyOutcome::published_version),
+        error,
+    };
+
+    report_change(clients, backend, environment, &event).await;
+
+    match result {
+        Ok(ApplyOutcome::Applied { .. }) => ram_size,
+        _ => memory_size,
+    }
+}
+
+/// Logs and reports the memory size the updater would switch to in the recommend-only mode
+async fn report_recommendation(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    clients: &AwsClients,
+    memory_size: i32,
+    ram_size: i32,
+    reason: RecommendationReason,
+) {
+    info!(
+        current_memory_size_mb = memory_size,
+        recommended_memory_size_mb = ram_size,
+        strategy = %environment.strategy,
+        reason = ?reason,
+        "Recommended a new RAM size, not applied in the recommend-only mode"
+    );
+
+    let event = UpdaterEvent::Recommendation {
+        current_memory_size_mb: memory_size,
+        memory_size_mb: ram_size,
+        reason,
+    };
+
+    report_event(clients, backend, environment, &event).await;
+}
+
+/// Checks whether a recommended change can be applied now
+async fn check_throttle(
+    environment: &LambdaEnvironment,
+    clients: &AwsClients,
+    polls: u32,
+) -> Result<(), Suppression> {
+    let config = &environment.throttle;
+
+    if polls < config.stable_polls {
+        return Err(Suppression::Unstable {
+            polls,
+            required: config.stable_polls,
+        });
+    }
+
+    let Some(changes) = &clients.changes else {
+        return Ok(());
+    };
+
+    match changes.load().await {
+        Ok(history) => throttle::evaluate(config, &history, chrono::Utc::now().timestamp()),
+        Err(e) => {
+            // Changing the memory without knowing the history may exceed the limits
+            error!("Failed to load the change history: {:?}", e);
+            Err(Suppression::Unknown)
+        }
+    }
+}
+
+/// Logs and reports the recommended change that is not applied
+async fn report_suppressed(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    clients: &AwsClients,
+    memory_size: i32,
+    ram_size: i32,
+    suppression: Suppression,
+) {
+    info!(
+        current_memory_size_mb = memory_size,
+        recommended_memory_size_mb = ram_size,
+        reason = %suppression,
+        "Suppressed the memory size change"
+    );
+
+    let event = UpdaterEvent::SuppressedUpdate {
+        current_memory_size_mb: memory_size,
+        memory_size_mb: ram_size,
+        reason: suppression.to_string(),
+    };
+
+    report_event(clients, backend, environment, &event).await;
+}
+
+/// Starts observing the applied change for regressions, the invocations of all the environments
+/// with the previous memory size are the baseline
+async fn observe_change(rollback: &mut RollbackTracker, previous_memory_size: i32, ram_size: i32) {
+    let observation = rollback.collector.take(previous_memory_size);
+
+    let baseline = match rollback
+        .store
+        .add_baseline(previous_memory_size, &observation)
+        .await
+    {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            // The check is still made, only on fewer invocations
+            error!("Failed to load the shared rollback baseline: {:?}", e);
+            observation
+        }
+    };
+
+    let change = Change {
+        previous_memory_size_mb: previous_memory_size,
+        memory_size_mb: ram_size,
+        applied_at: chrono::Utc::now().timestamp(),
+        baseline,
+        window: Observation::default(),
+    };
+
+    match rollback.store.start(&change).await {
+        Ok(_) => rollback.watching = Some(ram_size),
+        Err(e) => error!("Failed to start observing the memory change: {:?}", e),
+    }
+}
+
+/// Adds the invocations of this environment to the change under observation
+/// and reverts the change if it made things worse.
+/// Returns `true` if the updater must not make new changes now.
+async fn check_rollback(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    clients: &AwsClients,
+    rollback: &mut RollbackTracker,
+    current_memory_size: &mut i32,
+) -> bool {
+    let now = chrono::Utc::now().timestamp();
+
+    let state = match rollback.store.load().await {
+        Ok(state) => state,
+        Err(e) => {
+            // Changing the memory without knowing the state may repeat a reverted change
+            error!("Failed to load the rollback state: {:?}", e);
+            return true;
+        }
+    };
+
+    rollback.watching = state.change.as_ref().map(|change| change.memory_size_mb);
+
+    if let Some(pin) = state.active_pin(now) {
+        info!(
+            "Memory size is pinned to {} MB after a rollback for {} more seconds",
+            pin.memory_size_mb,
+            pin.until - now
+        );
+        return true;
+    }
+
+    let Some(change) = state.change else {
+        // In between the changes, the invocations are the baseline of the next one
+        let baseline = rollback.collector.take(*current_memory_size);
+
+        if let Err(e) = rollback
+            .store
+            .add_baseline(*current_memory_size, &baseline)
+            .await
+        {
+            error!("Failed to add to the rollback baseline: {:?}", e);
+        }
+
+        return false;
+    };
+
+    // Only the environments with the new memory size observe it
+    let window = if *current_memory_size == change.memory_size_mb {
+        rollback.collector.take(change.memory_size_mb)
+    } else {
+        Observation::default()
+    };
+
+    let change = match rollback.store.observe(&change, &window).await {
+        Ok(Some(change)) => change,
+        Ok(None) => {
+            // Another environment has confirmed or reverted it in the meantime,
+            // the next poll loads what replaced it
+            rollback.watching = None;
+            return true;
+        }
+        Err(e) => {
+            error!("Failed to add to the rollback window: {:?}", e);
+            return true;
+        }
+    };
+
+    let reason = match rollback::evaluate(&environment.rollback, &change, now) {
+        rollback::Verdict::Pending => return true,
+        rollback::Verdict::Confirm => {
+            rollback.watching = None;
+
+            match rollback.store.confirm(&change).await {
+                Ok(true) => info!("Memory change to {} MB is confirmed", change.memory_size_mb),
+                Ok(false) => return true,
+                Err(e) => error!("Failed to confirm the memory change: {:?}", e),
+            }
+            return false;
+        }
+        rollback::Verdict::Revert(reason) => reason,
+    };
+
+    let pinned_until = now + environment.rollback.cooldown.as_secs() as i64;
+
+    let pin = Pin {
+        memory_size_mb: change.previous_memory_size_mb,
+        until: pinned_until,
+    };
+
+    // Only the environment replacing the change with the pin reverts it
+    match rollback.store.pin(&change, pin).await {
+        Ok(true) => {}
+        Ok(false) => {
+            rollback.watching = None;
+            return true;
+        }
+        Err(e) => {
+            error!("Failed to save the rollback state: {:?}", e);
+            return true;
+        }
+    }
+
+    rollback.watching = None;
+
+    warn!(
+        "Reverting the memory size from {} MB to {} MB: {}",
+        change.memory_size_mb, change.previous_memory_size_mb, reason
+    );
+
+    // The lease may still be held by the environment that applied the change,
+    // the pin prevents repeated reverts instead
+    let result = update_and_publish(clients, environment, change.previous_memory_size_mb).await;
+
+    // A revert causes cold starts as well, so it counts against the change limits
+    if let (Ok(_), Some(changes)) = (&result, &clients.changes) {
+        changes.record().await;
+    }
+
+    let event = UpdaterEvent::Rollback {
+        previous_memory_size_mb: change.memory_size_mb,
+        memory_size_mb: change.previous_memory_size_mb,
+        reason,
+        pinned_until,
+        applied: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    };
+
+    report_change(clients, backend, environment, &event).await;
+
+    if result.is_ok() {
+        *current_memory_size = change.previous_memory_size_mb;
+    }
+
+    true
+}
+
+/// Collects the stats of the canary rollout in progress, then promotes
+/// or reverts it once the versions can be compared
+async fn handle_canary(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+    clients: &AwsClients,
+    (alias, store): (&str, &CanaryStore),
+    canary: &ActiveCanary,
+    stats: &FunctionStats,
+) {
+    let now = chrono::Utc::now().timestamp();
+
+    let reason = match canary::evaluate(&environment.canary, canary, stats, now) {
+        canary::Verdict::Pending => {
+            info!(
+                "Canary version {} with {} MB is being evaluated",
+                canary.canary_version, canary.canary_memory_size_mb
+            );
+            return;
+        }
+        canary::Verdict::Promote => None,
+        canary::Verdict::Revert(reason) => Some(reason),
+    };
+
+    // Only one environment completes the rollout
+    if let Some(coordinator) = &clients.coordinator {
+        match coordinator.acquire(canary.canary_memory_size_mb).await {
+            Ok(Lease::Acquired) => {}
+            Ok(Lease::Held { .. }) => return,
+            Err(e) => {
+                error!("Failed to complete the canary rollout: {:?}", e);
+                return;
+            }
+        }
+    }
+
+    let result = match &reason {
+        None => {
+            info!(
+                "Promoting canary version {} with {} MB",
+                canary.canary_version, canary.canary_memory_size_mb
+            );
+
+            match canary::promote(&clients.lambda_client, &environment.name, alias, canary).await {
+                Ok(_) => {
+                    update_ssm_parameter(
+                        &clients.ssm_client,
+                        environment.memory_parameter_name.as_deref(),
+                        canary.canary_memory_size_mb,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Some(reason) => {
+            warn!(
+                "Reverting canary version {} with {} MB: {}",
+                canary.canary_version, canary.canary_memory_size_mb, reason
+            );
+
+            // $LATEST gets the stable memory size back as well
+            match canary::revert(&clients.lambda_client, &environment.name, alias).await {
+                Ok(_) => {
+                    update_lambda_config(
+                        &clients.lambda_client,
+                        &environment.name,
+                        canary.stable_memory_size_mb,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    // Left in place on failure, so the rollout is completed again once the lease expires
+    match &result {
+        Ok(_) => {
+            if let Err(e) = store.finish(canary).await {
+                error!("Failed to remove the canary rollout: {:?}", e);
+            }
+        }
+        Err(e) => error!("Failed to complete the canary rollout: {:?}", e),
+    }
+
+    // Completing the rollout moves all the traffic, so it counts against the change limits
+    if let (Ok(_), Some(changes)) = (&result, &clients.changes) {
+        changes.record().await;
+    }
+
+    let event = UpdaterEvent::CanaryResult {
+        stable_version: canary.stable_version.clone(),
+        stable_memory_size_mb: canary.stable_memory_size_mb,
+        canary_version: canary.canary_version.clone(),
+        canary_memory_size_mb: canary.canary_memory_size_mb,
+        promoted: reason.is_none(),
+        reason,
+        error: result.err().map(|e| e.to_string()),
+    };
+
+    report_change(clients, backend, environment, &event).await;
+}
+
+/// Returns the alias to roll out the changes through and the store of the rollout
+/// if the canary rollout is enabled, never in the recommend-only mode
+fn canary_rollout<'a>(
+    environment: &'a LambdaEnvironment,
+    clients: &'a AwsClients,
+) -> Option<(&'a str, &'a CanaryStore)> {
+    if environment.update_mode == UpdateMode::RecommendOnly {
+        return None;
+    }
+
+    environment
+        .publish_alias
+        .as_deref()
+        .zip(clients.canary.as_ref())
+}
+
+/// Defines how a memory change reaches the traffic
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rollout {
+    /// All the traffic gets the new memory size at once
+    Full,
+    /// A share of the alias traffic gets the new memory size first, if the canary is enabled
+    Canary,
+}
+
+/// Result of a successful memory change attempt
+#[derive(Debug, PartialEq)]
+enum ApplyOutcome {
+    Applied {
+        /// Version published with the change in the publish mode
+        published_version: Option<String>,
+    },
+    /// Another execution environment is changing the memory size to this size or a larger one
+    Skipped { memory_size_mb: i32 },
+}
+
+impl ApplyOutcome {
+    fn published_version(&self) -> Option<String> {
+        match self {
+            ApplyOutcome::Applied { published_version } => published_version.clone(),
+            ApplyOutcome::Skipped { .. } => None,
+        }
+    }
+}
+
+/// Updates the Lambda function and the SSM parameter with the new RAM size,
+/// unless another execution environment is already doing it
+async fn apply_memory_size(
+    clients: &AwsClients,
+    environment: &LambdaEnvironment,
+    ram_size: i32,
+    rollout: Rollout,
+) -> eyre::Result<ApplyOutcome> {
+    if let Some(coordinator) = &clients.coordinator {
+        if let Lease::Held { memory_size_mb } = coordinator.acquire(ram_size).await? {
+            info!(
+                "Memory size change to {} MB is skipped, another environment is changing it to {} MB",
+                ram_size, memory_size_mb
+            );
+            return Ok(ApplyOutcome::Skipped { memory_size_mb });
+        }
+    }
+
+    let result = match (rollout, canary_rollout(environment, clients)) {
+        (Rollout::Canary, Some((alias, store))) => {
+            start_canary(clients, environment, alias, store, ram_size)
+                .await
+                .map(|canary| Some(canary.canary_version))
+        }
+        _ => update_and_publish(clients, environment, ram_size).await,
+    };
+
+    if let (Err(_), Some(coordinator)) = (&result, &clients.coordinator) {
+        coordinator.release().await;
+    }
+
+    if let (Ok(_), Some(changes)) = (&result, &clients.changes) {
+        changes.record().await;
+    }
+
+    result.map(|published_version| ApplyOutcome::Applied { published_version })
+}
+
+/// Changes $LATEST, publishes it and routes a share of the alias traffic to it.
+/// The SSM parameter is updated once the canary is promoted.
+async fn start_canary(
+    clients: &AwsClients,
+    environment: &LambdaEnvironment,
+    alias: &str,
+    store: &CanaryStore,
+    ram_size: i32,
+) -> eyre::Result<ActiveCanary> {
+    update_lambda_config(&clients.lambda_client, &environment.name, ram_size).await?;
+
+    canary::start(
+        &clients.lambda_client,
+        store,
+        &environment.name,
+        alias,
+        environment.canary.weight,
+        ram_size,
+    )
+    .await
+}
+
+/// Updates the function and the SSM parameter, then publishes a version in the publish mode
+async fn update_and_publish(
+    clients: &AwsClients,
+    environment: &LambdaEnvironment,
+    ram_size: i32,
+) -> eyre::Result<Option<String>> {
+    update_memory_size(clients, environment, ram_size).await?;
+
+    let version = publish_version(
+        &clients.lambda_client,
+        &environment.name,
+        environment.publish_alias.as_deref(),
+    )
+    .await?;
+
+    // Moving the alias ended the canary rollout in progress, it must not be evaluated anymore
+    if let (Some(_), Some(store)) = (&version, &clients.canary) {
+        match store.abort().await {
+            Ok(Some(canary)) => warn!(
+                "Canary version {} with {} MB is aborted by the change to {} MB",
+                canary.canary_version, canary.canary_memory_size_mb, ram_size
+            ),
+            Ok(None) => {}
+            Err(e) => error!("Failed to abort the canary rollout: {:?}", e),
+        }
+    }
+
+    Ok(version)
+}
+
+/// Publishes a new version with the updated configuration and moves the alias to it,
+/// so the traffic coming through the alias gets the new memory size.
+/// Does nothing and returns `None` if no alias is configured.
+async fn publish_version(
+    client: &LambdaClient,
+    function_name: &str,
+    alias: Option<&str>,
+) -> eyre::Result<Option<String>> {
+    let Some(alias) = alias else {
+        return Ok(None);
+    };
+
+    let version = client
+        .publish_version()
+        .function_name(function_name)
+        .description("Memory size changed by Optimeist")
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to publish a new version: {:?}", e))?
+        .version
+        .ok_or_eyre("Failed to get the published version")?;
+
+    info!(
+        "Published version {}, moving alias {} to it",
+        version, alias
+    );
+
+    client
+        .update_alias()
+        .function_name(function_name)
+        .name(alias)
+        .function_version(&version)
+        // Ends a canary rollout if any
+        .routing_config(canary::no_routing())
+        .send()
+        .await
+        .map_err(|e| {
+            eyre!(
+                "Failed to move alias {} to version {}: {:?}",
+                alias,
+                version,
+                e
+            )
+        })?;
+
+    Ok(Some(version))
+}
+
+/// Updates the Lambda function and the SSM parameter concurrently
+async fn update_memory_size(
+    clients: &AwsClients,
+    environment: &LambdaEnvironment,
+    ram_size: i32,
+) -> eyre::Result<()> {
+    let lambda = update_lambda_config(&clients.lambda_client, &environment.name, ram_size);
+    let ssm = update_ssm_parameter(
+        &clients.ssm_client,
+        environment.memory_parameter_name.as_deref(),
+        ram_size,
+    );
+
+    let (lambda_result, ssm_result) = tokio::join!(lambda, ssm);
+
+    match (lambda_result, ssm_result) {
+        (Ok(_), Ok(_)) => {
+            info!("Lambda and SSM parameters updated successfully");
+            Ok(())
+        }
+        (Err(e1), Ok(_)) => {
+            error!("Failed to update Lambda: {:?}", e1);
+            Err(e1)
+        }
+        (Ok(_), Err(e2)) => {
+            error!("Failed to update SSM: {:?}", e2);
+            Err(e2)
+        }
+        (Err(e1), Err(e2)) => {
+            error!("Failed to update Lambda and SSM: {:?} and {:?}", e1, e2);
+            Err(eyre!("{} and {}", e1, e2))
+        }
+    }
+}
+
+/// Records the memory change in the audit trail and reports it to the backend
+async fn report_change(
+    clients: &AwsClients,
+    backend: &BackendClient,
+    environment: &LambdaEnvironment,
+    event: &UpdaterEvent,
+) {
+    if let Some(record) = AuditRecord::from_event(environment, event) {
+        clients.audit.record(&record).await;
+    }
+
+    report_event(clients, backend, environment, event).await;
+}
+
+/// Reports the updater action to the backend and the enabled EMF and OTLP sinks,
+/// failures are only logged.
+///
+/// The backend receives `POST /events` with the bearer access token and the JSON body
+/// `{"event": {"type": "MEMORY_UPDATE", ...}, "meta": {...}, "timestampUs": "..."}`:
+/// `event` is an [`UpdaterEvent`] tagged by its SCREAMING_SNAKE_CASE `type`, `meta` is the
+/// [`LambdaEnvironment`] of the reporting environment. Any 2xx status is accepted and the
+/// response body is ignored, the request is retried according to the backend retry policy.
+async fn report_event(
+    clients: &AwsClients,
+    backend: &BackendClient,
+    environment: &LambdaEnvironment,
+    event: &UpdaterEvent,
+) {
+    emf::write_event(environment, event);
+
+    if let Some(otlp) = &clients.otlp {
+        otlp.export_event(event).await;
+    }
+
+    if !backend.is_enabled() {
+        return;
+    }
+
+    let data = EventData {
+        event,
+        meta: environment,
+        timestamp_us: chrono::Utc::now().timestamp_micros().to_string(),
+    };
+
+    if let Err(e) = backend.post("/events", &data).await {
+        error!("Failed to report the updater event: {}", e);
+    }
+}
+
 /// Updates the SSM parameter with the new RAM size
 async fn update_ssm_parameter(
     client: &SsmClient,
@@ -138,7 +1309,9 @@ async fn update_ssm_parameter(
     Ok(())
 }
 
-/// Updates the Lambda function configuration with the new RAM size
+/// Updates the Lambda function configuration with the new RAM size.
+/// Waits for the updates in progress, repeats the request on conflicts
+/// and completes when the function is updated.
 async fn update_lambda_config(
     client: &LambdaClient,
     function_name: &str,
@@ -146,15 +1319,88 @@ async fn update_lambda_config(
 ) -> eyre::Result<()> {
     info!("Updating Lambda function: {}", function_name);
 
-    client
-        .update_function_configuration()
-        .function_name(function_name)
-        .memory_size(ram_size)
-        .send()
-        .await
-        .map_err(|e| eyre!("Failed to update Lambda configuration: {:?}", e))?;
+    let mut attempt = 1;
 
-    Ok(())
+    loop {
+        // Deployments may be updating the function right now
+        wait_for_update(client, function_name).await?;
+
+        let result = client
+            .update_function_configuration()
+            .function_name(function_name)
+            .memory_size(ram_size)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => break,
+            Err(e)
+                if attempt < UPDATE_RETRY.max_attempts
+                    && e.as_service_error().is_some_and(|e| {
+                        e.is_resource_conflict_exception() || e.is_too_many_requests_exception()
+                    }) =>
+            {
+                let delay = UPDATE_RETRY.backoff(attempt);
+                warn!(
+                    "Lambda function is being updated, retrying in {:?} ({}/{})",
+                    delay, attempt, UPDATE_RETRY.max_attempts
+                );
+
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(eyre!("Failed to update Lambda configuration: {:?}", e)),
+        }
+    }
+
+    let configuration = wait_for_update(client, function_name).await?;
+
+    match configuration.last_update_status() {
+        Some(LastUpdateStatus::Failed) => Err(eyre!(
+            "Lambda configuration update failed: {} ({})",
+            configuration
+                .last_update_status_reason()
+                .unwrap_or("no reason given"),
+            configuration
+                .last_update_status_reason_code()
+                .map(|code| code.as_str())
+                .unwrap_or("no reason code")
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Waits until the function has no update in progress and returns its configuration
+async fn wait_for_update(
+    client: &LambdaClient,
+    function_name: &str,
+) -> eyre::Result<FunctionConfiguration> {
+    // Polls are counted instead of the elapsed time, which includes the frozen periods
+    for _ in 0..UPDATE_MAX_POLLS {
+        let configuration = client
+            .get_function()
+            .function_name(function_name)
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to get function details: {:?}", e))?
+            .configuration
+            .ok_or_eyre("Failed to get function configuration")?;
+
+        let is_updating = configuration.state() == Some(&State::Pending)
+            || configuration.last_update_status() == Some(&LastUpdateStatus::InProgress);
+
+        if !is_updating {
+            return Ok(configuration);
+        }
+
+        info!("Waiting for the Lambda function update in progress");
+        tokio::time::sleep(UPDATE_POLL_INTERVAL).await;
+    }
+
+    Err(eyre!(
+        "Lambda function is still being updated after {} checks",
+        UPDATE_MAX_POLLS
+    ))
 }
 
 /// Manages an updater task and provides a way to complete it
@@ -162,6 +1408,12 @@ async fn update_lambda_config(
 pub struct Updater {
     /// Arc to hold the inner state to prevent it from being moved while being borrowed
     inner: Arc<Mutex<Option<InnerState>>>,
+
+    /// Channel for signaling the Invoke events to the updater task
+    invokes: mpsc::Sender<oneshot::Sender<()>>,
+
+    /// Longest time an invocation waits for the poll
+    poll_budget: Duration,
 }
 
 #[derive(Debug)]
@@ -177,35 +1429,96 @@ struct InnerState {
 pub struct AwsClients {
     ssm_client: SsmClient,
     lambda_client: LambdaClient,
+    coordinator: Option<Coordinator>,
+    audit: AuditLog,
+    /// Applied changes, kept only if the cooldown or the daily budget is enabled
+    changes: Option<ChangeStore>,
+    /// Exports the updater decisions if the OTLP sink is enabled
+    otlp: Option<OtlpExporter>,
+    /// Canary rollout in progress, kept only if the canary is enabled with the lease table
+    canary: Option<CanaryStore>,
 }
 
 impl AwsClients {
-    pub fn new(aws_config: &SdkConfig) -> Self {
+    pub fn new(aws_config: &SdkConfig, environment: &LambdaEnvironment) -> Self {
         let ssm_client = SsmClient::new(aws_config);
         let lambda_client = LambdaClient::new(aws_config);
+        let coordinator = Coordinator::new(aws_config, environment);
+        let audit = AuditLog::new(environment.audit.clone(), ssm_client.clone());
+        let changes = environment.throttle.is_enabled().then(|| {
+            ChangeStore::new(
+                ssm_client.clone(),
+                environment.throttle.parameter_name.clone(),
+            )
+        });
+
+        let otlp = environment
+            .telemetry
+            .sinks
+            .contains(&SinkKind::Otlp)
+            .then(|| OtlpExporter::new(environment));
+
+        let canary = coordinator
+            .as_ref()
+            .filter(|_| environment.canary.is_enabled() && environment.publish_alias.is_some())
+            .map(|coordinator| CanaryStore::new(coordinator.shared("canary")));
 
         AwsClients {
             ssm_client,
             lambda_client,
+            coordinator,
+            audit,
+            changes,
+            otlp,
+            canary,
         }
     }
 }
 
 impl Updater {
-    pub fn new(aws_config: &SdkConfig, environment: LambdaEnvironment) -> Self {
+    pub fn new(
+        aws_config: &SdkConfig,
+        environment: LambdaEnvironment,
+        backend: BackendClient,
+        observations: mpsc::Receiver<Vec<Metrics>>,
+    ) -> Self {
         // Create a channel for gracefully shutting down a task to update the RAM size
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
-        let clients = AwsClients::new(aws_config);
+        // A single pending signal is enough, the updater only needs to know there was an invoke
+        let (invokes_tx, invokes_rx) = mpsc::channel(1);
+
+        let clients = AwsClients::new(aws_config, &environment);
+        let poll_budget = environment.poll.budget;
 
         // Spawn a task to update the RAM size periodically
-        let updater_handle = tokio::spawn(updater_task(shutdown_rx, environment, clients));
+        let updater_handle = tokio::spawn(updater_task(
+            shutdown_rx,
+            environment,
+            backend,
+            clients,
+            observations,
+            invokes_rx,
+        ));
 
         Updater {
             inner: Arc::new(Mutex::new(Some(InnerState {
                 handle: updater_handle,
                 tx: shutdown_tx,
             }))),
+            invokes: invokes_tx,
+            poll_budget,
+        }
+    }
+
+    /// Signals an Invoke event to the updater task, completes once the task handled it
+    async fn notify_invoke(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+
+        // A full channel means a signal is already pending, e.g. the task is busy with
+        // a rollback, so there is nothing to wait for
+        if self.invokes.try_send(done_tx).is_ok() {
+            let _ = done_rx.await;
         }
     }
 
@@ -241,6 +1554,8 @@ impl Updater {
     pub(crate) fn clone(&self) -> Self {
         Updater {
             inner: Arc::clone(&self.inner),
+            invokes: self.invokes.clone(),
+            poll_budget: self.poll_budget,
         }
     }
 }
@@ -0,0 +1,196 @@
+use crate::environment::GuardrailsConfig;
+use tracing::info;
+
+/// Smallest memory size supported by Lambda
+pub const LAMBDA_MIN_MEMORY_MB: i32 = 128;
+
+/// Largest memory size supported by Lambda
+pub const LAMBDA_MAX_MEMORY_MB: i32 = 10240;
+
+/// Fits the proposed memory size into the guardrails.
+/// Returns the adjusted size or the reason the change is rejected.
+pub fn guard_memory_size(
+    config: &GuardrailsConfig,
+    memory_size_mb: i32,
+    proposed_mb: i32,
+) -> Result<i32, String> {
+    if proposed_mb == memory_size_mb {
+        return Ok(proposed_mb);
+    }
+
+    let mut size = proposed_mb;
+
+    if size < config.min_memory_mb || size > config.max_memory_mb {
+        size = size.clamp(config.min_memory_mb, config.max_memory_mb);
+        info!(
+            "Proposed memory size {} MB is out of the {}-{} MB bounds, using {} MB",
+            proposed_mb, config.min_memory_mb, config.max_memory_mb, size
+        );
+    }
+
+    if config.max_change > 0.0 {
+        let current = memory_size_mb as f64;
+        let lowest = (current * (1.0 - config.max_change)).ceil() as i32;
+        let highest = (current * (1.0 + config.max_change)).floor() as i32;
+
+        if size < lowest || size > highest {
+            let limited = size.clamp(lowest, highest);
+            info!(
+                "Change from {} MB to {} MB exceeds {}% per update, using {} MB",
+                memory_size_mb,
+                size,
+                config.max_change * 100.0,
+                limited
+            );
+            size = limited;
+        }
+    }
+
+    if size % config.step_mb != 0 {
+        // Round towards the current size, so the change never exceeds the limits above
+        let rounded = if size > memory_size_mb {
+            size - size % config.step_mb
+        } else {
+            size + (config.step_mb - size % config.step_mb)
+        };
+
+        // The current size may be off the step, rounding must not turn the change around
+        if (size - memory_size_mb).signum() * (rounded - memory_size_mb).signum() < 0 {
+            return Err(format!(
+                "no multiple of {} MB between {} MB and {} MB",
+                config.step_mb, memory_size_mb, size
+            ));
+        }
+
+        info!(
+            "Memory size {} MB is not a multiple of {} MB, using {} MB",
+            size, config.step_mb, rounded
+        );
+        size = rounded;
+    }
+
+    if size < config.min_memory_mb || size > config.max_memory_mb {
+        return Err(format!(
+            "no multiple of {} MB fits the {}-{} MB bounds",
+            config.step_mb, config.min_memory_mb, config.max_memory_mb
+        ));
+    }
+
+    if size == memory_size_mb {
+        return Err(format!(
+            "{} MB can't be reached from {} MB within the guardrails",
+            proposed_mb, memory_size_mb
+        ));
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        min_memory_mb: i32,
+        max_memory_mb: i32,
+        step_mb: i32,
+        max_change: f64,
+    ) -> GuardrailsConfig {
+        GuardrailsConfig {
+            min_memory_mb,
+            max_memory_mb,
+            step_mb,
+            max_change,
+        }
+    }
+
+    fn lambda_bounds(step_mb: i32, max_change: f64) -> GuardrailsConfig {
+        config(
+            LAMBDA_MIN_MEMORY_MB,
+            LAMBDA_MAX_MEMORY_MB,
+            step_mb,
+            max_change,
+        )
+    }
+
+    #[test]
+    fn keeps_the_current_size() {
+        assert_eq!(
+            guard_memory_size(&lambda_bounds(64, 0.0), 1000, 1000),
+            Ok(1000)
+        );
+    }
+
+    #[test]
+    fn rounds_down_when_growing() {
+        assert_eq!(
+            guard_memory_size(&lambda_bounds(64, 0.0), 512, 700),
+            Ok(640)
+        );
+    }
+
+    #[test]
+    fn rounds_up_when_shrinking() {
+        assert_eq!(
+            guard_memory_size(&lambda_bounds(64, 0.0), 1024, 700),
+            Ok(704)
+        );
+    }
+
+    #[test]
+    fn rounds_from_an_off_step_current_size() {
+        assert_eq!(
+            guard_memory_size(&lambda_bounds(64, 0.0), 1000, 900),
+            Ok(960)
+        );
+        assert_eq!(
+            guard_memory_size(&lambda_bounds(64, 0.0), 1000, 1100),
+            Ok(1088)
+        );
+    }
+
+    #[test]
+    fn rejects_rounding_past_an_off_step_current_size() {
+        let result = guard_memory_size(&lambda_bounds(64, 0.0), 1000, 1010);
+        assert!(result
+            .unwrap_err()
+            .starts_with("no multiple of 64 MB between"));
+    }
+
+    #[test]
+    fn clamps_to_the_bounds() {
+        let config = config(256, 2048, 1, 0.0);
+
+        assert_eq!(guard_memory_size(&config, 512, 128), Ok(256));
+        assert_eq!(guard_memory_size(&config, 512, 4096), Ok(2048));
+    }
+
+    #[test]
+    fn rejects_when_no_step_fits_the_bounds() {
+        let config = config(200, 250, 128, 0.0);
+        let result = guard_memory_size(&config, 400, 220);
+
+        assert_eq!(
+            result,
+            Err("no multiple of 128 MB fits the 200-250 MB bounds".to_string())
+        );
+    }
+
+    #[test]
+    fn limits_the_change_per_update() {
+        let config = lambda_bounds(1, 0.5);
+
+        assert_eq!(guard_memory_size(&config, 1000, 3000), Ok(1500));
+        assert_eq!(guard_memory_size(&config, 1000, 128), Ok(500));
+    }
+
+    #[test]
+    fn rejects_a_size_that_can_not_be_reached() {
+        let result = guard_memory_size(&lambda_bounds(64, 0.0), 1024, 1050);
+
+        assert_eq!(
+            result,
+            Err("1050 MB can't be reached from 1024 MB within the guardrails".to_string())
+        );
+    }
+}
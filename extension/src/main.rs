@@ -1,14 +1,33 @@
+mod audit;
+mod backend;
+mod canary;
+mod coordination;
+mod emf;
 mod environment;
 mod events;
+mod guardrails;
+mod oom;
+mod otlp;
+mod recommender;
+mod rollback;
+mod shared;
+mod sink;
+mod spool;
+mod startup;
+mod stats;
 mod telemetry;
+mod telemetry_api;
+mod throttle;
+mod uploader;
 
-use crate::environment::LambdaEnvironment;
-use crate::events::{events_handler, Updater};
-use crate::telemetry::telemetry_handler;
+use crate::events::events_handler;
+use crate::startup::Startup;
+use crate::telemetry_api::TelemetryListener;
 use aws_config::{BehaviorVersion, Region};
 use eyre::Result;
-use lambda_extension::{service_fn, Error, Extension, LambdaEvent, SharedService};
+use lambda_extension::{service_fn, Error, Extension, LambdaEvent, NextEvent};
 use std::env;
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -32,24 +51,51 @@ async fn main() -> Result<(), Error> {
         .load()
         .await;
 
-    let telemetry_environment = LambdaEnvironment::new(&config).await?;
-    let events_environment = telemetry_environment.clone();
+    // Initialized in the background, the extension registers in the degraded mode meanwhile
+    let startup = Startup::new(config);
+    let events_startup = startup.clone();
 
-    let telemetry_processor = SharedService::new(service_fn(move |logs| {
-        telemetry_handler(telemetry_environment.clone(), logs)
-    }));
+    // Bound before the subscription, the Telemetry API starts sending the records right away.
+    // Without the listener the extension still registers, it just receives no telemetry.
+    let telemetry = match TelemetryListener::bind().await {
+        Ok(telemetry) => Some(telemetry),
+        Err(e) => {
+            error!("Continuing without telemetry: {:?}", e);
+            None
+        }
+    };
 
-    // Create a shared state to run the updater task and manage its shutdown
-    let updater = Updater::new(&config, events_environment);
+    let events_processor = service_fn(move |event: LambdaEvent| {
+        let services = events_startup
+            .services()
+            .map(|services| (services.updater.clone(), services.telemetry.clone()));
 
-    let events_processor =
-        service_fn(move |event: LambdaEvent| events_handler(updater.clone(), event));
+        async move {
+            match services {
+                Some((updater, telemetry)) => events_handler(updater, telemetry, event).await,
+                None => {
+                    if let NextEvent::Shutdown(_) = event.next {
+                        info!("Extension is shutting down in the degraded mode");
+                    }
+                    Ok(())
+                }
+            }
+        }
+    });
 
-    Extension::new()
-        .with_telemetry_processor(telemetry_processor)
+    let extension = Extension::new()
         .with_events_processor(events_processor)
-        .run()
+        .register()
         .await?;
 
+    if let Some(telemetry) = telemetry {
+        match telemetry.subscribe(&extension.extension_id).await {
+            Ok(()) => telemetry.serve(startup),
+            Err(e) => error!("Continuing without telemetry: {:?}", e),
+        }
+    }
+
+    extension.run().await?;
+
     Ok(())
 }
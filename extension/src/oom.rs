@@ -0,0 +1,26 @@
+use crate::environment::OomConfig;
+use crate::guardrails::LAMBDA_MAX_MEMORY_MB;
+use crate::telemetry::Metrics;
+use lambda_extension::Status;
+
+/// An invocation that used all the memory and failed has almost certainly run out of memory
+pub fn is_out_of_memory(metrics: &Metrics) -> bool {
+    let is_failed = |status: &Status| matches!(status, Status::Error | Status::Failure);
+
+    metrics.max_memory_used_mb >= metrics.memory_size_mb
+        && (is_failed(&metrics.status)
+            || metrics.runtime_done_status.as_ref().is_some_and(is_failed))
+}
+
+/// Returns the memory size to switch to after an out-of-memory invocation,
+/// `None` if upsizing is disabled or the ceiling is already reached
+pub fn upsized_memory(config: &OomConfig, memory_size_mb: i32) -> Option<i32> {
+    if config.factor <= 1.0 {
+        return None;
+    }
+
+    let ceiling = config.max_memory_mb.min(LAMBDA_MAX_MEMORY_MB);
+    let upsized = ((memory_size_mb as f64) * config.factor).ceil() as i32;
+
+    Some(upsized.min(ceiling)).filter(|&upsized| upsized > memory_size_mb)
+}
@@ -0,0 +1,407 @@
+use crate::audit::AuditRecord;
+use crate::environment::LambdaEnvironment;
+use crate::events::UpdaterEvent;
+use crate::sink::TelemetrySink;
+use crate::telemetry::{Batch, Metrics};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Longest time to wait for the collector to accept the metrics
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `AGGREGATION_TEMPORALITY_DELTA`, every counter data point is a single occurrence
+const DELTA_TEMPORALITY: i32 = 1;
+
+/// Sends the metrics to an OpenTelemetry collector with OTLP/HTTP in the JSON encoding
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    client: reqwest::Client,
+    environment: LambdaEnvironment,
+}
+
+impl OtlpExporter {
+    pub fn new(environment: &LambdaEnvironment) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(EXPORT_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            environment: environment.clone(),
+        }
+    }
+
+    /// Exports the metrics, failures are only logged
+    async fn export(&self, metrics: Vec<Value>) {
+        if metrics.is_empty() {
+            return;
+        }
+
+        let config = &self.environment.otlp;
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": self.resource_attributes() },
+                "scopeMetrics": [{
+                    "scope": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                    "metrics": metrics,
+                }],
+            }],
+        });
+
+        let mut request = self.client.post(&config.endpoint).json(&body);
+
+        for (name, value) in &config.headers {
+            request = request.header(name, value);
+        }
+
+        let result = match request.send().await {
+            Ok(response) => response.error_for_status().map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => info!("Exported metrics to {}", config.endpoint),
+            Err(e) => error!("Failed to export metrics to {}: {}", config.endpoint, e),
+        }
+    }
+
+    /// Describes the function with the OpenTelemetry semantic conventions
+    fn resource_attributes(&self) -> Vec<Value> {
+        let environment = &self.environment;
+
+        vec![
+            attribute("service.name", environment.name.as_str()),
+            attribute("cloud.provider", "aws"),
+            attribute("cloud.platform", "aws_lambda"),
+            attribute("cloud.region", environment.region.as_str()),
+            attribute("cloud.resource_id", environment.arn.as_str()),
+            attribute("faas.name", environment.name.as_str()),
+            attribute("faas.version", environment.version.as_str()),
+            attribute("faas.instance", environment.environment_id.as_str()),
+            attribute("optimeist.strategy", environment.strategy.to_string()),
+            attribute(
+                "optimeist.decision_mode",
+                environment.decision_mode.to_string(),
+            ),
+        ]
+    }
+
+    /// Exports the recommendations and the memory changes of the updater
+    pub async fn export_event(&self, event: &UpdaterEvent) {
+        let now = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+
+        let metrics = match event {
+            UpdaterEvent::Recommendation {
+                memory_size_mb,
+                reason,
+                ..
+            } => vec![gauge(
+                "optimeist.memory.recommended",
+                "MiBy",
+                vec![point(
+                    now,
+                    *memory_size_mb as f64,
+                    vec![attribute("reason", json!(reason))],
+                )],
+            )],
+
+            UpdaterEvent::SuppressedUpdate {
+                memory_size_mb,
+                reason,
+                ..
+            } => vec![counter(
+                "optimeist.memory.suppressed_changes",
+                vec![point(
+                    now,
+                    1.0,
+                    vec![
+                        attribute("memory_size_mb", memory_size_mb.to_string()),
+                        attribute("reason", reason.as_str()),
+                    ],
+                )],
+            )],
+
+            event => {
+                let Some(record) = AuditRecord::from_event(&self.environment, event) else {
+                    return;
+                };
+
+                let attributes = vec![
+                    attribute("source", json!(record.source)),
+                    attribute("success", record.success.to_string()),
+                    attribute(
+                        "previous_memory_size_mb",
+                        record.old_memory_size_mb.to_string(),
+                    ),
+                ];
+
+                vec![
+                    counter(
+                        "optimeist.memory.changes",
+                        vec![point(now, 1.0, attributes.clone())],
+                    ),
+                    gauge(
+                        "optimeist.memory.changed_size",
+                        "MiBy",
+                        vec![point(now, record.new_memory_size_mb as f64, attributes)],
+                    ),
+                ]
+            }
+        };
+
+        self.export(metrics).await;
+    }
+}
+
+/// Sends the per-invocation metrics to an OpenTelemetry collector
+#[derive(Debug)]
+pub struct OtlpSink {
+    pub exporter: OtlpExporter,
+}
+
+impl TelemetrySink for OtlpSink {
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()> {
+        Box::pin(self.exporter.export(invocation_metrics(&batch.metrics)))
+    }
+}
+
+/// Converts the invocations into a gauge per measurement with a data point per invocation
+fn invocation_metrics(metrics: &[Metrics]) -> Vec<Value> {
+    if metrics.is_empty() {
+        return vec![];
+    }
+
+    let points = |value: fn(&Metrics) -> Option<f64>| -> Vec<Value> {
+        metrics
+            .iter()
+            .filter_map(|metrics| {
+                let time = metrics.timestamp_us.parse::<i64>().unwrap_or_default() * 1000;
+                // The request id isn't an attribute, a unique value per point would make
+                // a separate time series of every invocation
+                let attributes = vec![attribute("faas.invocation_status", json!(metrics.status))];
+
+                value(metrics).map(|value| point(time, value, attributes))
+            })
+            .collect()
+    };
+
+    let mut gauges = vec![
+        gauge(
+            "optimeist.invocation.duration",
+            "ms",
+            points(|metrics| Some(metrics.duration_ms)),
+        ),
+        gauge(
+            "optimeist.invocation.billed_duration",
+            "ms",
+            points(|metrics| Some(metrics.billed_duration_ms as f64)),
+        ),
+        gauge(
+            "optimeist.invocation.max_memory_used",
+            "MiBy",
+            points(|metrics| Some(metrics.max_memory_used_mb as f64)),
+        ),
+        gauge(
+            "optimeist.invocation.memory_size",
+            "MiBy",
+            points(|metrics| Some(metrics.memory_size_mb as f64)),
+        ),
+    ];
+
+    // Only the cold starts have the init duration
+    let init_durations = points(|metrics| metrics.init_duration_ms);
+
+    if !init_durations.is_empty() {
+        gauges.push(gauge(
+            "optimeist.invocation.init_duration",
+            "ms",
+            init_durations,
+        ));
+    }
+
+    gauges
+}
+
+fn gauge(name: &str, unit: &str, points: Vec<Value>) -> Value {
+    json!({
+        "name": name,
+        "unit": unit,
+        "gauge": { "dataPoints": points },
+    })
+}
+
+/// Each point counts a single event, so its delta interval starts and ends at the event time
+fn counter(name: &str, mut points: Vec<Value>) -> Value {
+    for point in &mut points {
+        if let Some(time) = point.get("timeUnixNano").cloned() {
+            point["startTimeUnixNano"] = time;
+        }
+    }
+
+    json!({
+        "name": name,
+        "unit": "1",
+        "sum": {
+            "dataPoints": points,
+            "aggregationTemporality": DELTA_TEMPORALITY,
+            "isMonotonic": true,
+        },
+    })
+}
+
+fn point(time_unix_nano: i64, value: f64, attributes: Vec<Value>) -> Value {
+    json!({
+        // 64-bit integers are strings in the JSON encoding of OTLP
+        "timeUnixNano": time_unix_nano.to_string(),
+        "asDouble": value,
+        "attributes": attributes,
+    })
+}
+
+fn attribute(key: &str, value: impl Into<Value>) -> Value {
+    let value = match value.into() {
+        Value::String(value) => value,
+        value => value.to_string(),
+    };
+
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::parse_otlp_headers;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    type Received = Arc<Mutex<Option<(Request<()>, Value)>>>;
+
+    /// Accepts a single request and keeps its head and JSON body
+    async fn collector() -> (String, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let received = Received::default();
+        let store = received.clone();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+
+            let service = service_fn(move |request: Request<Incoming>| {
+                let store = store.clone();
+
+                async move {
+                    let (head, body) = request.into_parts();
+                    let body = body.collect().await.unwrap().to_bytes();
+                    let body = serde_json::from_slice(&body).unwrap();
+
+                    *store.lock().unwrap() = Some((Request::from_parts(head, ()), body));
+                    Ok::<_, Infallible>(Response::new(Empty::<Bytes>::new()))
+                }
+            });
+
+            http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        (endpoint, received)
+    }
+
+    #[tokio::test]
+    async fn exports_invocation_metrics() {
+        let (endpoint, received) = collector().await;
+
+        let mut environment = LambdaEnvironment::for_test();
+        environment.otlp.endpoint = endpoint;
+        environment.otlp.headers = parse_otlp_headers("api-key=a%20b%3Dc");
+
+        let metrics: Metrics = serde_json::from_value(json!({
+            "requestId": "a",
+            "durationMs": 12.5,
+            "billedDurationMs": 13,
+            "memorySizeMB": 512,
+            "maxMemoryUsedMB": 128,
+            "status": "success",
+            "timestampUs": "1700000000000000",
+            "receivedAtUs": "1700000000100000",
+        }))
+        .unwrap();
+
+        let batch = Batch {
+            metrics: vec![metrics],
+            ..Batch::default()
+        };
+
+        let sink = OtlpSink {
+            exporter: OtlpExporter::new(&environment),
+        };
+        sink.send(&batch).await;
+
+        let (request, body) = received.lock().unwrap().take().expect("No export request");
+
+        assert_eq!(request.uri().path(), "/v1/metrics");
+        assert_eq!(request.headers()["api-key"], "a b=c");
+        assert_eq!(request.headers()["content-type"], "application/json");
+
+        let resource_metrics = &body["resourceMetrics"][0];
+        let resource = &resource_metrics["resource"]["attributes"];
+        assert!(resource
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "key": "faas.name", "value": { "stringValue": "test" } })));
+
+        let metrics = resource_metrics["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let names: Vec<&str> = metrics
+            .iter()
+            .map(|metric| metric["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "optimeist.invocation.duration",
+                "optimeist.invocation.billed_duration",
+                "optimeist.invocation.max_memory_used",
+                "optimeist.invocation.memory_size",
+            ]
+        );
+
+        assert_eq!(
+            metrics[0]["gauge"]["dataPoints"],
+            json!([{
+                "timeUnixNano": "1700000000000000000",
+                "asDouble": 12.5,
+                "attributes": [
+                    { "key": "faas.invocation_status", "value": { "stringValue": "success" } },
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn counter_points_start_at_their_time() {
+        let metric = counter("test", vec![point(1_700_000_000_000_000_000, 1.0, vec![])]);
+
+        assert_eq!(
+            metric["sum"]["dataPoints"][0]["startTimeUnixNano"],
+            "1700000000000000000"
+        );
+        assert_eq!(
+            metric["sum"]["dataPoints"][0]["timeUnixNano"],
+            "1700000000000000000"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::environment::{LocalConfig, Strategy};
+use crate::stats::{FunctionStats, SizeStats, StatsStore};
+use crate::telemetry::Metrics;
+use tracing::{error, info};
+
+/// Memory sizes the local recommender moves between
+const CANDIDATE_SIZES: [i32; 12] = [
+    128, 256, 512, 768, 1024, 1536, 2048, 3008, 4096, 6144, 8192, 10240,
+];
+
+/// A size must be better than the current one by this share to switch to it,
+/// so noise in the metrics doesn't make the memory flip-flop
+const IMPROVEMENT_THRESHOLD: f64 = 0.05;
+
+/// Memory usage above this share of a smaller size makes it too risky to try
+const MEMORY_HEADROOM: f64 = 0.9;
+
+/// Computes the next memory size from the metrics the extension receives,
+/// without calling the backend
+#[derive(Debug)]
+pub struct LocalRecommender {
+    config: LocalConfig,
+    store: StatsStore,
+
+    /// Metrics received since the last recommendation, not yet added to the store
+    pending: FunctionStats,
+}
+
+impl LocalRecommender {
+    pub fn new(config: LocalConfig, store: StatsStore) -> Self {
+        Self {
+            config,
+            store,
+            pending: FunctionStats::default(),
+        }
+    }
+
+    pub fn observe(&mut self, metrics: &[Metrics]) {
+        self.pending.observe(metrics);
+    }
+
+    /// Stores the pending metrics, so they are not lost when the environment shuts down
+    pub async fn flush(&mut self) {
+        if let Err(e) = self.store.merge(&self.pending).await {
+            error!("Failed to store the function stats: {:?}", e);
+            return;
+        }
+
+        self.pending = FunctionStats::default();
+    }
+
+    /// Returns the memory size to switch to, the current one if no change is needed
+    pub async fn recommend(&mut self, memory_size: i32, strategy: &Strategy) -> i32 {
+        let stats = match self.store.merge(&self.pending).await {
+            Ok(stats) => {
+                self.pending = FunctionStats::default();
+                stats
+            }
+            Err(e) => {
+                // Keep the pending metrics to store them next time
+                error!("Failed to store the function stats: {:?}", e);
+                let mut stats = self.pending.clone();
+
+                if let Ok(stored) = self.store.load().await {
+                    stats.merge(&stored);
+                }
+
+                stats
+            }
+        };
+
+        let recommendation = decide(&self.config, &stats, memory_size, strategy);
+
+        info!(
+            "Local recommendation for {} MB with {} strategy: {} MB",
+            memory_size, strategy, recommendation
+        );
+
+        recommendation
+    }
+}
+
+/// Picks the best sampled size for the strategy. If the current one is the best, explores
+/// the next unsampled size in the direction the strategy favours, as long as the last step
+/// in that direction improved the score.
+fn decide(
+    config: &LocalConfig,
+    stats: &FunctionStats,
+    memory_size: i32,
+    strategy: &Strategy,
+) -> i32 {
+    let sampled = |size: i32| {
+        stats
+            .sizes
+            .get(&size)
+            .filter(|stats| stats.invocations >= config.min_samples)
+    };
+
+    // Not enough data for the current size yet
+    let Some(current) = sampled(memory_size) else {
+        return memory_size;
+    };
+
+    let lower = CANDIDATE_SIZES
+        .iter()
+        .rev()
+        .copied()
+        .find(|&size| size < memory_size);
+
+    let higher = CANDIDATE_SIZES
+        .iter()
+        .copied()
+        .find(|&size| size > memory_size);
+
+    let favours_lower = match strategy {
+        Strategy::Cost => true,
+        Strategy::Speed => false,
+        Strategy::Balanced(weight) => *weight >= 0.5,
+    };
+
+    // The size to explore next and the one the exploration came from
+    let (ahead, behind) = if favours_lower {
+        (
+            lower.filter(|&size| is_safe_to_lower(current, size)),
+            higher,
+        )
+    } else {
+        (higher, lower)
+    };
+
+    // Sizes failing more often than the current one are not an option
+    let candidates: Vec<(i32, &SizeStats)> = stats
+        .sizes
+        .iter()
+        .filter(|(_, stats)| stats.invocations >= config.min_samples)
+        .filter(|(_, stats)| stats.out_of_memory == 0)
+        .filter(|(_, stats)| stats.failure_rate() <= current.failure_rate())
+        .map(|(size, stats)| (*size, stats))
+        .collect();
+
+    let min_cost = candidates
+        .iter()
+        .map(|(size, stats)| cost(*size, stats))
+        .fold(f64::INFINITY, f64::min);
+
+    let min_duration = candidates
+        .iter()
+        .map(|(_, stats)| stats.mean_duration_ms())
+        .fold(f64::INFINITY, f64::min);
+
+    // Lower is better, both parts are normalized by the best value among the candidates
+    let score = |size: i32, stats: &SizeStats| {
+        let cost = cost(size, stats) / min_cost.max(f64::EPSILON);
+        let speed = stats.mean_duration_ms() / min_duration.max(f64::EPSILON);
+
+        match strategy {
+            Strategy::Cost => cost,
+            Strategy::Speed => speed,
+            Strategy::Balanced(weight) => weight * cost + (1.0 - weight) * speed,
+        }
+    };
+
+    let current_score = score(memory_size, current);
+    let is_better = |other_score: f64| other_score < current_score * (1.0 - IMPROVEMENT_THRESHOLD);
+
+    let best = candidates
+        .iter()
+        .map(|(size, stats)| (*size, score(*size, stats)))
+        .filter(|(_, score)| is_better(*score))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    if let Some((size, _)) = best {
+        return size;
+    }
+
+    // No further steps once the last one didn't pay off, e.g. more memory no longer speeds
+    // the function up, so the memory doesn't climb all the way to the limit
+    let is_improving = behind
+        .and_then(|size| candidates.iter().find(|(candidate, _)| *candidate == size))
+        .is_none_or(|(size, stats)| {
+            current_score < score(*size, stats) * (1.0 - IMPROVEMENT_THRESHOLD)
+        });
+
+    match ahead {
+        Some(size) if is_improving && sampled(size).is_none() => size,
+        _ => memory_size,
+    }
+}
+
+/// Compute cost of an average invocation in MB-ms
+fn cost(memory_size: i32, stats: &SizeStats) -> f64 {
+    memory_size as f64 * stats.mean_billed_duration_ms()
+}
+
+/// A smaller size is only tried if the function doesn't get close to its limit
+fn is_safe_to_lower(current: &SizeStats, size: i32) -> bool {
+    current.out_of_memory == 0
+        && (current.max_memory_used_mb as f64) < size as f64 * MEMORY_HEADROOM
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LocalConfig {
+        LocalConfig {
+            min_samples: 10,
+            stats_parameter_name: "/optimeist/test/stats".to_string(),
+        }
+    }
+
+    fn size_stats(duration_ms: f64, max_memory_used_mb: u64) -> SizeStats {
+        SizeStats {
+            invocations: 100,
+            duration_ms_sum: duration_ms * 100.0,
+            billed_duration_ms_sum: duration_ms * 100.0,
+            max_memory_used_mb,
+            ..SizeStats::default()
+        }
+    }
+
+    fn stats(sizes: &[(i32, SizeStats)]) -> FunctionStats {
+        FunctionStats {
+            sizes: sizes.iter().cloned().collect(),
+        }
+    }
+
+    #[test]
+    fn keeps_the_size_until_it_is_sampled() {
+        let stats = stats(&[(512, size_stats(100.0, 100))]);
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Cost), 1024);
+    }
+
+    #[test]
+    fn cost_explores_the_lower_size() {
+        let stats = stats(&[(1024, size_stats(100.0, 100))]);
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Cost), 768);
+    }
+
+    #[test]
+    fn cost_never_explores_the_higher_size() {
+        // 768 MB is sampled and more expensive, 1536 MB is not sampled
+        let stats = stats(&[
+            (768, size_stats(150.0, 100)),
+            (1024, size_stats(100.0, 100)),
+        ]);
+
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Cost), 1024);
+    }
+
+    #[test]
+    fn cost_moves_back_to_the_cheaper_size() {
+        let stats = stats(&[
+            (768, size_stats(150.0, 100)),
+            (1024, size_stats(100.0, 100)),
+        ]);
+
+        assert_eq!(decide(&config(), &stats, 768, &Strategy::Cost), 1024);
+    }
+
+    #[test]
+    fn cost_keeps_exploring_while_it_gets_cheaper() {
+        let stats = stats(&[
+            (768, size_stats(100.0, 100)),
+            (1024, size_stats(100.0, 100)),
+        ]);
+
+        assert_eq!(decide(&config(), &stats, 768, &Strategy::Cost), 512);
+    }
+
+    #[test]
+    fn does_not_lower_close_to_the_memory_limit() {
+        let stats = stats(&[(1024, size_stats(100.0, 700))]);
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Cost), 1024);
+    }
+
+    #[test]
+    fn speed_explores_the_higher_size() {
+        let stats = stats(&[(1024, size_stats(100.0, 100))]);
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Speed), 1536);
+    }
+
+    #[test]
+    fn speed_stops_once_more_memory_does_not_help() {
+        let stats = stats(&[
+            (1024, size_stats(100.0, 100)),
+            (1536, size_stats(98.0, 100)),
+        ]);
+
+        assert_eq!(decide(&config(), &stats, 1536, &Strategy::Speed), 1536);
+    }
+
+    #[test]
+    fn speed_moves_to_the_faster_size() {
+        let stats = stats(&[
+            (1024, size_stats(100.0, 100)),
+            (1536, size_stats(60.0, 100)),
+        ]);
+
+        assert_eq!(decide(&config(), &stats, 1024, &Strategy::Speed), 1536);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::coordination::Coordinator;
+use crate::environment::RollbackConfig;
+use crate::shared::{Counters, SharedTable};
+use crate::stats::SizeStats;
+use crate::telemetry::Metrics;
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Epoch of the baseline counters, they are kept until the function is changed to the size again
+const BASELINE_EPOCH: &str = "baseline";
+
+/// Prefix of the shared counters of the duration histogram buckets
+const BUCKET_PREFIX: &str = "bucket";
+
+/// Upper bound of a histogram bucket is this factor of the previous one,
+/// so percentiles are accurate within 10%
+const BUCKET_GROWTH: f64 = 1.1;
+
+/// Histogram of the invocation durations with exponentially growing buckets
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DurationHistogram {
+    /// Number of invocations per bucket index, only non-empty buckets are kept
+    buckets: BTreeMap<u32, u64>,
+}
+
+impl DurationHistogram {
+    pub fn observe(&mut self, duration_ms: f64) {
+        let index = (duration_ms.max(0.0) + 1.0).log(BUCKET_GROWTH).floor() as u32;
+        *self.buckets.entry(index).or_default() += 1;
+    }
+
+    fn to_counters(&self, counters: &mut Counters) {
+        for (index, count) in &self.buckets {
+            counters.insert(format!("{BUCKET_PREFIX}{index}"), *count as f64);
+        }
+    }
+
+    fn from_counters(counters: &Counters) -> Self {
+        let buckets = counters
+            .iter()
+            .filter_map(|(name, count)| {
+                let index = name.strip_prefix(BUCKET_PREFIX)?.parse().ok()?;
+                Some((index, *count as u64))
+            })
+            .collect();
+
+        Self { buckets }
+    }
+
+    /// Returns the upper bound of the bucket with the given percentile, e.g. 0.95 for p95
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        let total: u64 = self.buckets.values().sum();
+        let rank = (total as f64 * percentile).ceil() as u64;
+        let mut seen = 0;
+
+        for (index, count) in &self.buckets {
+            seen += count;
+
+            if seen >= rank {
+                return BUCKET_GROWTH.powi(*index as i32 + 1) - 1.0;
+            }
+        }
+
+        0.0
+    }
+}
+
+/// Invocations with the same memory size observed for a regression check
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Observation {
+    pub stats: SizeStats,
+    pub durations: DurationHistogram,
+}
+
+impl Observation {
+    pub fn observe(&mut self, metrics: &Metrics) {
+        self.stats.observe(metrics);
+        self.durations.observe(metrics.duration_ms);
+    }
+
+    fn to_counters(&self) -> Counters {
+        let mut counters = Counters::new();
+        self.stats.to_counters("", &mut counters);
+        self.durations.to_counters(&mut counters);
+        counters
+    }
+
+    fn from_counters(counters: &Counters) -> Self {
+        Self {
+            stats: SizeStats::from_counters("", counters),
+            durations: DurationHistogram::from_counters(counters),
+        }
+    }
+}
+
+/// Collects the observations of this execution environment per memory size
+#[derive(Clone, Debug, Default)]
+pub struct Collector {
+    sizes: BTreeMap<i32, Observation>,
+}
+
+impl Collector {
+    pub fn observe(&mut self, metrics: &[Metrics]) {
+        for metrics in metrics {
+            self.sizes
+                .entry(metrics.memory_size_mb as i32)
+                .or_default()
+                .observe(metrics);
+        }
+    }
+
+    /// Returns the observations of the memory size collected so far and forgets them
+    pub fn take(&mut self, memory_size_mb: i32) -> Observation {
+        self.sizes.remove(&memory_size_mb).unwrap_or_default()
+    }
+}
+
+/// Memory change being observed for regressions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    #[serde(rename = "previousMemorySizeMB")]
+    pub previous_memory_size_mb: i32,
+    #[serde(rename = "memorySizeMB")]
+    pub memory_size_mb: i32,
+    /// Unix timestamp in seconds when the change was applied
+    pub applied_at: i64,
+    /// Invocations with the previous memory size before the change, of all the environments
+    pub baseline: Observation,
+    /// Invocations with the new memory size after the change, added up in the shared counters
+    #[serde(skip)]
+    pub window: Observation,
+}
+
+impl Change {
+    /// Identifies the change, so the invocations of one change are never added to another
+    fn epoch(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.applied_at, self.previous_memory_size_mb, self.memory_size_mb
+        )
+    }
+}
+
+/// Memory size the updater keeps after a rollback
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pin {
+    #[serde(rename = "memorySizeMB")]
+    pub memory_size_mb: i32,
+    /// Unix timestamp in seconds when the pin expires
+    pub until: i64,
+}
+
+/// Rollback state shared by the execution environments
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackState {
+    pub change: Option<Change>,
+    pub pin: Option<Pin>,
+}
+
+impl RollbackState {
+    /// Returns the pin that is not expired yet
+    pub fn active_pin(&self, now: i64) -> Option<&Pin> {
+        self.pin.as_ref().filter(|pin| pin.until > now)
+    }
+}
+
+/// Outcome of the regression check of a change
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    /// The observation window is not over yet
+    Pending,
+    /// No regression during the observation window
+    Confirm,
+    Revert(String),
+}
+
+/// Compares the invocations after the change with the ones before it
+pub fn evaluate(config: &RollbackConfig, change: &Change, now: i64) -> Verdict {
+    let baseline = &change.baseline;
+    let window = &change.window;
+
+    // Running out of memory is a reason to revert right away
+    if window.stats.out_of_memory > 0 {
+        return Verdict::Revert(format!(
+            "{} invocations ran out of memory",
+            window.stats.out_of_memory
+        ));
+    }
+
+    let is_over = now - change.applied_at >= config.window.as_secs() as i64;
+
+    // The thresholds are checked on enough invocations of both sizes
+    if baseline.stats.invocations < config.min_samples
+        || window.stats.invocations < config.min_samples
+    {
+        return if is_over {
+            Verdict::Confirm
+        } else {
+            Verdict::Pending
+        };
+    }
+
+    let error_rate_increase = window.stats.failure_rate() - baseline.stats.failure_rate();
+
+    if error_rate_increase > config.max_error_rate_increase {
+        return Verdict::Revert(format!(
+            "error and timeout rate grew from {:.2}% to {:.2}%",
+            baseline.stats.failure_rate() * 100.0,
+            window.stats.failure_rate() * 100.0
+        ));
+    }
+
+    let baseline_p95 = baseline.durations.percentile(0.95);
+    let window_p95 = window.durations.percentile(0.95);
+
+    if window_p95 > baseline_p95 * (1.0 + config.max_p95_increase) {
+        return Verdict::Revert(format!(
+            "p95 duration grew from {:.1} ms to {:.1} ms",
+            baseline_p95, window_p95
+        ));
+    }
+
+    if is_over {
+        Verdict::Confirm
+    } else {
+        Verdict::Pending
+    }
+}
+
+/// Keeps the rollback state in the lease table shared by the execution environments.
+///
+/// The invocations are added to the window atomically, and the change is confirmed or reverted
+/// by a single environment. A change that has ended is never brought back by a late update.
+/// The baseline of every memory size is added up in an item of its own in between the changes.
+#[derive(Clone, Debug)]
+pub struct RollbackStore {
+    coordinator: Coordinator,
+    table: SharedTable,
+}
+
+impl RollbackStore {
+    pub fn new(coordinator: &Coordinator) -> Self {
+        Self {
+            coordinator: coordinator.clone(),
+            table: coordinator.shared("rollback"),
+        }
+    }
+
+    fn baseline_table(&self, memory_size_mb: i32) -> SharedTable {
+        self.coordinator
+            .shared(&format!("baseline#{memory_size_mb}"))
+    }
+
+    /// Adds the invocations to the baseline of the memory size and returns the updated baseline
+    pub async fn add_baseline(
+        &self,
+        memory_size_mb: i32,
+        observation: &Observation,
+    ) -> Result<Observation> {
+        let item = self
+            .baseline_table(memory_size_mb)
+            .accumulate(BASELINE_EPOCH, &observation.to_counters())
+            .await?;
+
+        Ok(Observation::from_counters(&item.counters))
+    }
+
+    /// Loads the state, a missing item is treated as nothing to observe
+    pub async fn load(&self) -> Result<RollbackState> {
+        match self.table.load().await? {
+            Some(item) => parse_state(&item.state, &item.counters),
+            None => Ok(RollbackState::default()),
+        }
+    }
+
+    /// Starts observing the change, replacing the previous state. The baseline of the new
+    /// memory size starts over, the invocations from before the change are outdated.
+    pub async fn start(&self, change: &Change) -> Result<()> {
+        let state = RollbackState {
+            change: Some(change.clone()),
+            pin: None,
+        };
+
+        self.baseline_table(change.memory_size_mb)
+            .start(BASELINE_EPOCH, String::new())
+            .await?;
+
+        self.table
+            .start(&change.epoch(), serde_json::to_string(&state)?)
+            .await
+    }
+
+    /// Adds the invocations to the window of the change and returns the updated change.
+    /// Returns `None` if the change is not observed anymore.
+    pub async fn observe(&self, change: &Change, window: &Observation) -> Result<Option<Change>> {
+        let Some(item) = self
+            .table
+            .add(&change.epoch(), &window.to_counters())
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(parse_state(&item.state, &item.counters)?.change)
+    }
+
+    /// Ends the observation of the change.
+    /// Returns `false` if another environment has already ended it.
+    pub async fn confirm(&self, change: &Change) -> Result<bool> {
+        self.table.remove(&change.epoch()).await
+    }
+
+    /// Replaces the change with the pin of the previous memory size.
+    /// Returns `false` if another environment has already ended the change.
+    pub async fn pin(&self, change: &Change, pin: Pin) -> Result<bool> {
+        let state = RollbackState {
+            change: None,
+            pin: Some(pin),
+        };
+
+        self.table
+            .replace(
+                &change.epoch(),
+                &format!("pin:{}", change.epoch()),
+                serde_json::to_string(&state)?,
+            )
+            .await
+    }
+}
+
+fn parse_state(state: &str, counters: &Counters) -> Result<RollbackState> {
+    let mut state: RollbackState =
+        serde_json::from_str(state).wrap_err("Failed to parse the rollback state")?;
+
+    if let Some(change) = state.change.as_mut() {
+        change.window = Observation::from_counters(counters);
+    }
+
+    Ok(state)
+}
+
+/// Rollback state of this execution environment
+#[derive(Clone, Debug)]
+pub struct RollbackTracker {
+    pub store: RollbackStore,
+    pub collector: Collector,
+    /// Memory size under observation according to the last loaded state
+    pub watching: Option<i32>,
+}
+
+impl RollbackTracker {
+    pub fn new(store: RollbackStore) -> Self {
+        Self {
+            store,
+            collector: Collector::default(),
+            watching: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn config() -> RollbackConfig {
+        RollbackConfig {
+            window: Duration::from_secs(600),
+            min_samples: 10,
+            max_p95_increase: 0.2,
+            max_error_rate_increase: 0.01,
+            cooldown: Duration::from_secs(3600),
+        }
+    }
+
+    fn observation(duration_ms: f64, invocations: u64, errors: u64) -> Observation {
+        let mut observation = Observation::default();
+
+        for _ in 0..invocations {
+            observation.durations.observe(duration_ms);
+        }
+
+        observation.stats.invocations = invocations;
+        observation.stats.errors = errors;
+        observation.stats.duration_ms_sum = duration_ms * invocations as f64;
+        observation
+    }
+
+    fn change(age_seconds: i64, baseline: Observation, window: Observation) -> Change {
+        Change {
+            previous_memory_size_mb: 512,
+            memory_size_mb: 256,
+            applied_at: NOW - age_seconds,
+            baseline,
+            window,
+        }
+    }
+
+    #[test]
+    fn percentile_is_the_bucket_upper_bound() {
+        let mut histogram = DurationHistogram::default();
+
+        for duration_ms in 1..=100 {
+            histogram.observe(duration_ms as f64);
+        }
+
+        let p95 = histogram.percentile(0.95);
+        assert!((95.0..=95.0 * BUCKET_GROWTH).contains(&p95), "p95 {p95}");
+
+        let p50 = histogram.percentile(0.5);
+        assert!((50.0..=50.0 * BUCKET_GROWTH).contains(&p50), "p50 {p50}");
+    }
+
+    #[test]
+    fn percentile_of_empty_histogram_is_zero() {
+        assert_eq!(DurationHistogram::default().percentile(0.95), 0.0);
+    }
+
+    #[test]
+    fn observation_survives_the_shared_counters() {
+        let mut original = observation(120.0, 20, 2);
+        original.stats.timeouts = 1;
+        original.durations.observe(5000.0);
+
+        let restored = Observation::from_counters(&original.to_counters());
+
+        assert_eq!(restored.stats.invocations, 20);
+        assert_eq!(restored.stats.errors, 2);
+        assert_eq!(restored.stats.timeouts, 1);
+        assert_eq!(restored.durations.buckets, original.durations.buckets);
+    }
+
+    #[test]
+    fn pending_until_the_window_is_over() {
+        let change = change(60, observation(100.0, 20, 0), observation(100.0, 20, 0));
+        assert_eq!(evaluate(&config(), &change, NOW), Verdict::Pending);
+    }
+
+    #[test]
+    fn confirms_after_the_window() {
+        let change = change(600, observation(100.0, 20, 0), observation(110.0, 20, 0));
+        assert_eq!(evaluate(&config(), &change, NOW), Verdict::Confirm);
+    }
+
+    #[test]
+    fn confirms_without_enough_samples() {
+        let change = change(600, observation(100.0, 20, 0), observation(500.0, 5, 5));
+        assert_eq!(evaluate(&config(), &change, NOW), Verdict::Confirm);
+    }
+
+    #[test]
+    fn reverts_out_of_memory_right_away() {
+        let mut window = observation(100.0, 1, 1);
+        window.stats.out_of_memory = 1;
+
+        let change = change(10, Observation::default(), window);
+        assert!(matches!(
+            evaluate(&config(), &change, NOW),
+            Verdict::Revert(_)
+        ));
+    }
+
+    #[test]
+    fn reverts_when_the_error_rate_grows() {
+        let change = change(60, observation(100.0, 100, 0), observation(100.0, 100, 2));
+        assert!(matches!(
+            evaluate(&config(), &change, NOW),
+            Verdict::Revert(reason) if reason.starts_with("error and timeout rate")
+        ));
+    }
+
+    #[test]
+    fn reverts_when_the_p95_grows() {
+        let change = change(60, observation(100.0, 20, 0), observation(130.0, 20, 0));
+        assert!(matches!(
+            evaluate(&config(), &change, NOW),
+            Verdict::Revert(reason) if reason.starts_with("p95 duration")
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use eyre::{eyre, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Numeric attributes added up by the execution environments
+pub type Counters = BTreeMap<String, f64>;
+
+/// State kept in an item of the lease table
+#[derive(Clone, Debug, Default)]
+pub struct SharedItem {
+    /// Identifies what the counters are collected for, e.g. a memory change
+    pub epoch: String,
+    /// JSON written when the epoch starts
+    pub state: String,
+    pub counters: Counters,
+}
+
+/// Keeps the state shared by the execution environments in the lease table, next to the lease.
+///
+/// An SSM parameter can only be overwritten, so concurrent environments lose each other's
+/// updates. Here the counters are added atomically, and every write is conditional on the epoch,
+/// so a stale environment can't bring back a state another one has already finished.
+#[derive(Clone, Debug)]
+pub struct SharedTable {
+    client: DynamoClient,
+    table_name: String,
+    key: String,
+}
+
+impl SharedTable {
+    pub fn new(client: DynamoClient, table_name: String, key: String) -> Self {
+        Self {
+            client,
+            table_name,
+            key,
+        }
+    }
+
+    /// Loads the item, `None` if nothing is stored
+    pub async fn load(&self) -> Result<Option<SharedItem>> {
+        let response = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.key.clone()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to get the shared state {}: {:?}", self.key, e))?;
+
+        response.item.map(parse_item).transpose()
+    }
+
+    /// Starts a new epoch, replacing whatever is stored
+    pub async fn start(&self, epoch: &str, state: String) -> Result<()> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(self.item(epoch, state)))
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to start the shared state {}: {:?}", self.key, e))?;
+
+        Ok(())
+    }
+
+    /// Adds the counters to the epoch and returns the updated item.
+    /// Returns `None` if the epoch is over.
+    pub async fn add(&self, epoch: &str, counters: &Counters) -> Result<Option<SharedItem>> {
+        if counters.is_empty() {
+            return Ok(self.load().await?.filter(|item| item.epoch == epoch));
+        }
+
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.key.clone()))
+            .condition_expression("#epoch = :epoch")
+            .expression_attribute_names("#epoch", "epoch")
+            .expression_attribute_values(":epoch", AttributeValue::S(epoch.to_string()))
+            .return_values(ReturnValue::AllNew);
+
+        let mut actions = Vec::with_capacity(counters.len());
+
+        for (index, (name, value)) in counters.iter().enumerate() {
+            actions.push(format!("#c{index} :c{index}"));
+            request = request
+                .expression_attribute_names(format!("#c{index}"), counter_attribute(name))
+                .expression_attribute_values(
+                    format!(":c{index}"),
+                    AttributeValue::N(value.to_string()),
+                );
+        }
+
+        let result = request
+            .update_expression(format!("ADD {}", actions.join(", ")))
+            .send()
+            .await;
+
+        match result {
+            Ok(response) => response.attributes.map(parse_item).transpose(),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(eyre!(
+                "Failed to update the shared state {}: {:?}",
+                self.key,
+                e
+            )),
+        }
+    }
+
+    /// Adds the counters to the epoch, starting it if nothing is stored yet,
+    /// and returns the updated item. For counters kept for good, e.g. the function stats.
+    pub async fn accumulate(&self, epoch: &str, counters: &Counters) -> Result<SharedItem> {
+        if counters.is_empty() {
+            return Ok(self.load().await?.unwrap_or_default());
+        }
+
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.key.clone()))
+            .condition_expression("attribute_not_exists(#epoch) OR #epoch = :epoch")
+            .expression_attribute_names("#epoch", "epoch")
+            .expression_attribute_values(":epoch", AttributeValue::S(epoch.to_string()))
+            .return_values(ReturnValue::AllNew);
+
+        let mut actions = Vec::with_capacity(counters.len());
+
+        for (index, (name, value)) in counters.iter().enumerate() {
+            actions.push(format!("#c{index} :c{index}"));
+            request = request
+                .expression_attribute_names(format!("#c{index}"), counter_attribute(name))
+                .expression_attribute_values(
+                    format!(":c{index}"),
+                    AttributeValue::N(value.to_string()),
+                );
+        }
+
+        let response = request
+            .update_expression(format!("SET #epoch = :epoch ADD {}", actions.join(", ")))
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to update the shared state {}: {:?}", self.key, e))?;
+
+        Ok(response
+            .attributes
+            .map(parse_item)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    /// Raises the counter to the value if it's lower, for the values that can't be added up.
+    /// Returns `false` if the stored value is already higher.
+    pub async fn raise(&self, epoch: &str, name: &str, value: f64) -> Result<bool> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.key.clone()))
+            .update_expression("SET #c = :c")
+            .condition_expression("#epoch = :epoch AND (attribute_not_exists(#c) OR #c < :c)")
+            .expression_attribute_names("#epoch", "epoch")
+            .expression_attribute_names("#c", counter_attribute(name))
+            .expression_attribute_values(":epoch", AttributeValue::S(epoch.to_string()))
+            .expression_attribute_values(":c", AttributeValue::N(value.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(eyre!(
+                "Failed to raise the shared counter {} of {}: {:?}",
+                name,
+                self.key,
+                e
+            )),
+        }
+    }
+
+    /// Ends the epoch and starts the next one.
+    /// Returns `false` if another environment has already ended it.
+    pub async fn replace(&self, epoch: &str, next_epoch: &str, state: String) -> Result<bool> {
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(self.item(next_epoch, state)))
+            .condition_expression("#epoch = :epoch")
+            .expression_attribute_names("#epoch", "epoch")
+            .expression_attribute_values(":epoch", AttributeValue::S(epoch.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(eyre!(
+                "Failed to replace the shared state {}: {:?}",
+                self.key,
+                e
+            )),
+        }
+    }
+
+    /// Ends the epoch and removes the item.
+    /// Returns `false` if another environment has already ended it.
+    pub async fn remove(&self, epoch: &str) -> Result<bool> {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("functionArn", AttributeValue::S(self.key.clone()))
+            .condition_expression("#epoch = :epoch")
+            .expression_attribute_names("#epoch", "epoch")
+            .expression_attribute_values(":epoch", AttributeValue::S(epoch.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(eyre!(
+                "Failed to remove the shared state {}: {:?}",
+                self.key,
+                e
+            )),
+        }
+    }
+
+    fn item(&self, epoch: &str, state: String) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            (
+                "functionArn".to_string(),
+                AttributeValue::S(self.key.clone()),
+            ),
+            ("epoch".to_string(), AttributeValue::S(epoch.to_string())),
+            ("state".to_string(), AttributeValue::S(state)),
+        ])
+    }
+}
+
+/// Counters are prefixed, so they never clash with the other attributes
+fn counter_attribute(name: &str) -> String {
+    format!("c.{name}")
+}
+
+fn parse_item(item: HashMap<String, AttributeValue>) -> Result<SharedItem> {
+    let mut shared = SharedItem::default();
+
+    for (name, value) in item {
+        match (name.as_str(), value) {
+            ("epoch", AttributeValue::S(epoch)) => shared.epoch = epoch,
+            ("state", AttributeValue::S(state)) => shared.state = state,
+            (name, AttributeValue::N(value)) => {
+                if let Some(counter) = name.strip_prefix("c.") {
+                    let value = value
+                        .parse()
+                        .wrap_err_with(|| format!("Invalid counter {name}: {value}"))?;
+                    shared.counters.insert(counter.to_string(), value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(shared)
+}
@@ -0,0 +1,231 @@
+use crate::backend::BackendClient;
+use crate::emf::EmfSink;
+use crate::environment::LambdaEnvironment;
+use crate::otlp::{OtlpExporter, OtlpSink};
+use crate::spool::Spool;
+use crate::telemetry::{replay_spool, upload, Batch, InitMetrics, Metrics, RestoreMetrics};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+/// Maximum number of spooled batches replayed after a single uploaded batch
+const REPLAY_LIMIT: usize = 10;
+
+/// Destination of the telemetry records built from the Telemetry API
+pub trait TelemetrySink: Debug + Send + Sync {
+    /// Writes the batch, failures are handled by the sink itself
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()>;
+
+    /// Writes everything the sink still keeps, called when the extension is shutting down
+    fn flush(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Kind of a telemetry sink selected with `OPTIMEIST_TELEMETRY_SINKS`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SinkKind {
+    /// Optimeist backend, the metrics failed to upload are spooled
+    Backend,
+    /// JSON lines written to stdout, so they end up in CloudWatch Logs
+    Stdout,
+    /// JSON lines appended to a local file
+    File,
+    /// CloudWatch Embedded Metric Format written to stdout, includes the updater decisions
+    Emf,
+    /// OpenTelemetry collector over OTLP/HTTP, includes the updater decisions
+    Otlp,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "BACKEND" => Ok(SinkKind::Backend),
+            "STDOUT" => Ok(SinkKind::Stdout),
+            "FILE" => Ok(SinkKind::File),
+            "EMF" => Ok(SinkKind::Emf),
+            "OTLP" => Ok(SinkKind::Otlp),
+            _ => Err(format!("Unknown telemetry sink: {s}")),
+        }
+    }
+}
+
+/// Creates the configured sinks. The backend sink is skipped if the backend is disabled.
+pub fn build_sinks(
+    environment: &LambdaEnvironment,
+    backend: &BackendClient,
+) -> Vec<Box<dyn TelemetrySink>> {
+    let config = &environment.telemetry;
+    let mut sinks: Vec<Box<dyn TelemetrySink>> = vec![];
+
+    for kind in &config.sinks {
+        match kind {
+            SinkKind::Backend if backend.is_enabled() => sinks.push(Box::new(BackendSink {
+                backend: backend.clone(),
+                environment: environment.clone(),
+                spool: Spool::new(
+                    config.spool_dir.clone(),
+                    config.spool_max_bytes,
+                    config.spool_eviction,
+                ),
+            })),
+            SinkKind::Backend => {}
+            SinkKind::Stdout => sinks.push(Box::new(StdoutSink {
+                environment: environment.clone(),
+            })),
+            SinkKind::File => sinks.push(Box::new(FileSink {
+                environment: environment.clone(),
+                path: config.file_path.clone(),
+            })),
+            SinkKind::Emf => sinks.push(Box::new(EmfSink {
+                environment: environment.clone(),
+            })),
+            SinkKind::Otlp => sinks.push(Box::new(OtlpSink {
+                exporter: OtlpExporter::new(environment),
+            })),
+        }
+    }
+
+    sinks
+}
+
+/// Sends the batches to the backend in chunks, spools and replays the failed ones
+#[derive(Debug)]
+struct BackendSink {
+    backend: BackendClient,
+    environment: LambdaEnvironment,
+    spool: Spool,
+}
+
+impl TelemetrySink for BackendSink {
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            // The spool is replayed only while the backend is reachable
+            if upload(&self.backend, &self.environment, &self.spool, batch.clone()).await {
+                replay_spool(
+                    &self.backend,
+                    &self.environment,
+                    &self.spool,
+                    Some(REPLAY_LIMIT),
+                )
+                .await;
+            }
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, ()> {
+        Box::pin(replay_spool(
+            &self.backend,
+            &self.environment,
+            &self.spool,
+            None,
+        ))
+    }
+}
+
+/// Writes every record as a JSON line to stdout
+#[derive(Debug)]
+struct StdoutSink {
+    environment: LambdaEnvironment,
+}
+
+impl TelemetrySink for StdoutSink {
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let lines = json_lines(&self.environment, batch);
+
+            // Locked once, so the lines of the batch are not interleaved with the logs
+            if let Err(e) = std::io::stdout().lock().write_all(lines.as_bytes()) {
+                error!("Failed to write the telemetry to stdout: {:?}", e);
+            }
+        })
+    }
+}
+
+/// Appends every record as a JSON line to a local file
+#[derive(Debug)]
+struct FileSink {
+    environment: LambdaEnvironment,
+    path: PathBuf,
+}
+
+impl TelemetrySink for FileSink {
+    fn send<'a>(&'a self, batch: &'a Batch) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let lines = json_lines(&self.environment, batch);
+
+            let result = async {
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?
+                    .write_all(lines.as_bytes())
+                    .await
+            }
+            .await;
+
+            if let Err(e) = result {
+                error!(
+                    "Failed to write the telemetry to {}: {:?}",
+                    self.path.display(),
+                    e
+                );
+            }
+        })
+    }
+}
+
+/// Telemetry record written as a single JSON line
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLine<'a> {
+    function_name: &'a str,
+    function_version: &'a str,
+    #[serde(flatten)]
+    record: Record<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "recordType", rename_all = "SCREAMING_SNAKE_CASE")]
+enum Record<'a> {
+    Invocation(&'a Metrics),
+    Init(&'a InitMetrics),
+    Restore(&'a RestoreMetrics),
+}
+
+fn json_lines(environment: &LambdaEnvironment, batch: &Batch) -> String {
+    let records = batch
+        .metrics
+        .iter()
+        .map(Record::Invocation)
+        .chain(batch.inits.iter().map(Record::Init))
+        .chain(batch.restores.iter().map(Record::Restore));
+
+    let mut lines = String::new();
+
+    for record in records {
+        let line = JsonLine {
+            function_name: &environment.name,
+            function_version: &environment.version,
+            record,
+        };
+
+        match serde_json::to_string(&line) {
+            Ok(line) => {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+            Err(e) => error!("Failed to serialize a telemetry record: {:?}", e),
+        }
+    }
+
+    lines
+}
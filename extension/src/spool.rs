@@ -0,0 +1,179 @@
+use eyre::{eyre, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+
+/// Sequence number to keep file names unique within the same microsecond
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Defines which records are dropped once the spool reaches its size cap
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpoolEviction {
+    /// Remove the oldest spooled batches to make room for the new one
+    #[default]
+    DropOldest,
+    /// Keep the spooled batches and reject the new one
+    DropNewest,
+}
+
+impl FromStr for SpoolEviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "DROP_OLDEST" => Ok(SpoolEviction::DropOldest),
+            "DROP_NEWEST" => Ok(SpoolEviction::DropNewest),
+            _ => Err(format!("Unknown spool eviction policy: {s}")),
+        }
+    }
+}
+
+/// Bounded on-disk storage for batches that failed to upload.
+/// Every batch is stored in a separate file, file names are ordered by the creation time.
+#[derive(Clone, Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    eviction: SpoolEviction,
+
+    /// Serializes access to the spool directory between the telemetry and events handlers
+    lock: Arc<Mutex<()>>,
+}
+
+/// Exclusive access to the spool, obtained with [`Spool::lock`]
+pub struct SpoolGuard<'a> {
+    spool: &'a Spool,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf, max_bytes: u64, eviction: SpoolEviction) -> Self {
+        Self {
+            dir,
+            max_bytes,
+            eviction,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Spooling is disabled when the size cap is set to zero
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Waits for exclusive access to the spool directory
+    pub async fn lock(&self) -> SpoolGuard<'_> {
+        SpoolGuard {
+            spool: self,
+            _guard: self.lock.lock().await,
+        }
+    }
+}
+
+impl SpoolGuard<'_> {
+    /// Writes a batch to the spool evicting old batches according to the policy
+    pub async fn push<T: Serialize>(&self, batch: &T) -> Result<()> {
+        let spool = self.spool;
+
+        if !spool.is_enabled() {
+            return Err(eyre!("Spool is disabled"));
+        }
+
+        let data = serde_json::to_vec(batch).wrap_err("Failed to serialize the batch")?;
+        let size = data.len() as u64;
+
+        if size > spool.max_bytes {
+            return Err(eyre!(
+                "Batch of {} bytes exceeds the spool size cap of {} bytes",
+                size,
+                spool.max_bytes
+            ));
+        }
+
+        fs::create_dir_all(&spool.dir)
+            .await
+            .wrap_err("Failed to create the spool directory")?;
+
+        let mut entries = self.entries().await?;
+        let mut total: u64 = entries.iter().map(|(_, size)| size).sum();
+
+        while total + size > spool.max_bytes {
+            match spool.eviction {
+                SpoolEviction::DropNewest => {
+                    return Err(eyre!("Spool is full ({} bytes)", total));
+                }
+                SpoolEviction::DropOldest => {
+                    let (path, evicted) = entries.remove(0);
+                    warn!("Spool is full, evicting {}", path.display());
+                    self.remove(&path).await?;
+                    total -= evicted;
+                }
+            }
+        }
+
+        let name = format!(
+            "{:020}-{:06}",
+            chrono::Utc::now().timestamp_micros(),
+            SEQUENCE.fetch_add(1, Ordering::Relaxed) % 1_000_000
+        );
+
+        // Write to a temporary file first, so a partially written batch is never replayed
+        let tmp_path = spool.dir.join(format!("{name}.tmp"));
+        let path = spool.dir.join(format!("{name}.json"));
+
+        fs::write(&tmp_path, data)
+            .await
+            .wrap_err("Failed to write the spool file")?;
+
+        fs::rename(&tmp_path, &path)
+            .await
+            .wrap_err("Failed to rename the spool file")?;
+
+        info!("Spooled a batch of {} bytes to {}", size, path.display());
+        Ok(())
+    }
+
+    /// Lists spooled batches with their sizes, the oldest first
+    pub async fn entries(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut entries = vec![];
+
+        let mut dir = match fs::read_dir(&self.spool.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e).wrap_err("Failed to read the spool directory"),
+        };
+
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().is_some_and(|ext| ext == "json") {
+                entries.push((path, entry.metadata().await?.len()));
+            }
+        }
+
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Reads a spooled batch
+    pub async fn read<T: DeserializeOwned>(&self, path: &Path) -> Result<T> {
+        let data = fs::read(path)
+            .await
+            .wrap_err("Failed to read the spool file")?;
+
+        serde_json::from_slice(&data).wrap_err("Failed to parse the spool file")
+    }
+
+    /// Removes a spooled batch
+    pub async fn remove(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path)
+            .await
+            .wrap_err("Failed to remove the spool file")
+    }
+}
@@ -0,0 +1,107 @@
+use crate::backend::{BackendClient, RetryPolicy};
+use crate::environment::LambdaEnvironment;
+use crate::events::Updater;
+use crate::sink::build_sinks;
+use crate::telemetry::{InvocationTracker, TelemetryContext};
+use crate::uploader::Uploader;
+use aws_config::SdkConfig;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Number of telemetry batches waiting to be processed by the updater
+const OBSERVATIONS_CAPACITY: usize = 64;
+
+/// Longest time an initialization attempt may take before it is retried
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delays between the initialization attempts in the degraded mode, retried until it succeeds
+const INIT_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: u32::MAX,
+    base_delay: Duration::from_secs(5),
+    max_delay: Duration::from_secs(300),
+};
+
+/// Telemetry processing and the updater, available once the extension is initialized
+#[derive(Debug)]
+pub struct Services {
+    pub telemetry: TelemetryContext,
+    pub updater: Updater,
+}
+
+/// Initialization state shared by the handlers.
+///
+/// The extension registers before the initialization finishes and even if it fails:
+/// the function must never fail or start slower because of its optimizer. Until the services
+/// are initialized the extension runs in the degraded mode, the telemetry is dropped and
+/// no memory changes are made.
+#[derive(Clone, Debug, Default)]
+pub struct Startup {
+    services: Arc<OnceLock<Services>>,
+}
+
+impl Startup {
+    /// Starts initializing the services in the background, so the extension registers
+    /// without waiting for the AWS APIs and starts in the degraded mode
+    pub fn new(config: SdkConfig) -> Self {
+        let startup = Startup::default();
+        tokio::spawn(init(config, startup.clone()));
+        startup
+    }
+
+    /// Returns the services, `None` in the degraded mode
+    pub fn services(&self) -> Option<&Services> {
+        self.services.get()
+    }
+}
+
+/// Initializes the services, repeats it with a backoff until it succeeds
+async fn init(config: SdkConfig, startup: Startup) {
+    for attempt in 0.. {
+        if attempt > 0 {
+            tokio::time::sleep(INIT_RETRY.backoff(attempt)).await;
+        }
+
+        match try_init(&config).await {
+            Ok(services) => {
+                if attempt > 0 {
+                    info!("Extension is initialized after {} retries", attempt);
+                }
+                let _ = startup.services.set(services);
+                return;
+            }
+            Err(e) if attempt == 0 => warn!(
+                "Extension is running in the degraded mode, telemetry and memory updates \
+                are disabled: {:?}",
+                e
+            ),
+            Err(e) => warn!("Failed to initialize the extension: {:?}", e),
+        }
+    }
+}
+
+async fn try_init(config: &SdkConfig) -> eyre::Result<Services> {
+    let environment = tokio::time::timeout(INIT_TIMEOUT, LambdaEnvironment::new(config))
+        .await
+        .map_err(|_| eyre::eyre!("Initialization timed out after {:?}", INIT_TIMEOUT))??;
+
+    // A single client is shared to reuse connections to the backend
+    let backend = BackendClient::new(&environment);
+
+    // Metrics of the invocations are forwarded to the updater to react on them
+    let (observations_tx, observations_rx) = mpsc::channel(OBSERVATIONS_CAPACITY);
+
+    let uploader = Uploader::new(&environment.telemetry, build_sinks(&environment, &backend));
+
+    let telemetry = TelemetryContext {
+        uploader,
+        tracker: InvocationTracker::new(),
+        observations: observations_tx,
+    };
+
+    // Create a shared state to run the updater task and manage its shutdown
+    let updater = Updater::new(config, environment, backend, observations_rx);
+
+    Ok(Services { telemetry, updater })
+}
@@ -0,0 +1,277 @@
+use crate::oom::is_out_of_memory;
+use crate::shared::{Counters, SharedTable};
+use crate::telemetry::Metrics;
+use aws_sdk_ssm::types::ParameterType;
+use aws_sdk_ssm::Client as SsmClient;
+use eyre::{eyre, Context, Result};
+use lambda_extension::Status;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Shared counter of the max memory used, raised instead of added up
+const MAX_MEMORY_USED_COUNTER: &str = "maxMemoryUsedMB";
+
+/// Epoch of the function stats in the lease table, they are never reset
+const STATS_EPOCH: &str = "stats";
+
+/// Aggregated metrics of the invocations with the same memory size
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeStats {
+    pub invocations: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub out_of_memory: u64,
+    pub duration_ms_sum: f64,
+    pub billed_duration_ms_sum: f64,
+    #[serde(rename = "maxMemoryUsedMB")]
+    pub max_memory_used_mb: u64,
+}
+
+impl SizeStats {
+    pub fn observe(&mut self, metrics: &Metrics) {
+        self.invocations += 1;
+        self.duration_ms_sum += metrics.duration_ms;
+        self.billed_duration_ms_sum += metrics.billed_duration_ms as f64;
+        self.max_memory_used_mb = self.max_memory_used_mb.max(metrics.max_memory_used_mb);
+
+        match metrics.status {
+            Status::Error | Status::Failure => self.errors += 1,
+            Status::Timeout => self.timeouts += 1,
+            Status::Success => {}
+        }
+
+        if is_out_of_memory(metrics) {
+            self.out_of_memory += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &SizeStats) {
+        self.invocations += other.invocations;
+        self.errors += other.errors;
+        self.timeouts += other.timeouts;
+        self.out_of_memory += other.out_of_memory;
+        self.duration_ms_sum += other.duration_ms_sum;
+        self.billed_duration_ms_sum += other.billed_duration_ms_sum;
+        self.max_memory_used_mb = self.max_memory_used_mb.max(other.max_memory_used_mb);
+    }
+
+    /// Adds the stats to the counters shared by the execution environments.
+    /// The max memory used can't be added up, so it's not shared.
+    pub fn to_counters(&self, prefix: &str, counters: &mut Counters) {
+        let values = [
+            ("invocations", self.invocations as f64),
+            ("errors", self.errors as f64),
+            ("timeouts", self.timeouts as f64),
+            ("outOfMemory", self.out_of_memory as f64),
+            ("durationMsSum", self.duration_ms_sum),
+            ("billedDurationMsSum", self.billed_duration_ms_sum),
+        ];
+
+        for (name, value) in values {
+            if value != 0.0 {
+                counters.insert(format!("{prefix}{name}"), value);
+            }
+        }
+    }
+
+    pub fn from_counters(prefix: &str, counters: &Counters) -> Self {
+        let counter = |name: &str| {
+            counters
+                .get(&format!("{prefix}{name}"))
+                .copied()
+                .unwrap_or_default()
+        };
+
+        Self {
+            invocations: counter("invocations") as u64,
+            errors: counter("errors") as u64,
+            timeouts: counter("timeouts") as u64,
+            out_of_memory: counter("outOfMemory") as u64,
+            duration_ms_sum: counter("durationMsSum"),
+            billed_duration_ms_sum: counter("billedDurationMsSum"),
+            // Only the function stats keep it, raised separately
+            max_memory_used_mb: counter(MAX_MEMORY_USED_COUNTER) as u64,
+        }
+    }
+
+    pub fn mean_duration_ms(&self) -> f64 {
+        self.duration_ms_sum / self.invocations.max(1) as f64
+    }
+
+    pub fn mean_billed_duration_ms(&self) -> f64 {
+        self.billed_duration_ms_sum / self.invocations.max(1) as f64
+    }
+
+    /// Share of the invocations that failed or timed out
+    pub fn failure_rate(&self) -> f64 {
+        (self.errors + self.timeouts) as f64 / self.invocations.max(1) as f64
+    }
+}
+
+/// Statistics of the function per memory size
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionStats {
+    pub sizes: BTreeMap<i32, SizeStats>,
+}
+
+impl FunctionStats {
+    pub fn observe(&mut self, metrics: &[Metrics]) {
+        for metrics in metrics {
+            self.sizes
+                .entry(metrics.memory_size_mb as i32)
+                .or_default()
+                .observe(metrics);
+        }
+    }
+
+    pub fn merge(&mut self, other: &FunctionStats) {
+        for (memory_size, stats) in &other.sizes {
+            self.sizes.entry(*memory_size).or_default().merge(stats);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// Converts the stats into the counters shared by the execution environments,
+    /// prefixed with the memory size
+    pub fn to_counters(&self) -> Counters {
+        let mut counters = Counters::new();
+
+        for (memory_size, stats) in &self.sizes {
+            stats.to_counters(&format!("{memory_size}."), &mut counters);
+        }
+
+        counters
+    }
+
+    pub fn from_counters(counters: &Counters) -> Self {
+        let memory_sizes: BTreeSet<i32> = counters
+            .keys()
+            .filter_map(|name| name.split_once('.')?.0.parse().ok())
+            .collect();
+
+        let sizes = memory_sizes
+            .into_iter()
+            .map(|memory_size| {
+                let stats = SizeStats::from_counters(&format!("{memory_size}."), counters);
+                (memory_size, stats)
+            })
+            .collect();
+
+        Self { sizes }
+    }
+}
+
+/// Keeps the function statistics, so they outlive the execution environments
+/// recycled on every memory change and are shared between the concurrent ones
+#[derive(Clone, Debug)]
+pub enum StatsStore {
+    /// Counters in the lease table, the environments add their statistics atomically
+    Shared(SharedTable),
+    /// SSM parameter, used without the lease table. Concurrent environments may overwrite
+    /// each other, so the statistics are approximate.
+    Ssm {
+        client: SsmClient,
+        parameter_name: String,
+    },
+}
+
+impl StatsStore {
+    /// Loads the stored statistics, nothing stored is treated as no statistics
+    pub async fn load(&self) -> Result<FunctionStats> {
+        match self {
+            StatsStore::Shared(table) => Ok(table
+                .load()
+                .await?
+                .map(|item| FunctionStats::from_counters(&item.counters))
+                .unwrap_or_default()),
+            StatsStore::Ssm {
+                client,
+                parameter_name,
+            } => load_parameter(client, parameter_name).await,
+        }
+    }
+
+    /// Adds the local statistics to the stored ones and returns the result
+    pub async fn merge(&self, local: &FunctionStats) -> Result<FunctionStats> {
+        match self {
+            StatsStore::Shared(table) => merge_shared(table, local).await,
+            StatsStore::Ssm {
+                client,
+                parameter_name,
+            } => {
+                let mut stats = load_parameter(client, parameter_name).await?;
+
+                if local.is_empty() {
+                    return Ok(stats);
+                }
+
+                stats.merge(local);
+                save_parameter(client, parameter_name, &stats).await?;
+
+                Ok(stats)
+            }
+        }
+    }
+}
+
+async fn merge_shared(table: &SharedTable, local: &FunctionStats) -> Result<FunctionStats> {
+    let item = table.accumulate(STATS_EPOCH, &local.to_counters()).await?;
+    let mut stats = FunctionStats::from_counters(&item.counters);
+
+    for (memory_size, local) in &local.sizes {
+        let stored = stats.sizes.entry(*memory_size).or_default();
+
+        if local.max_memory_used_mb > stored.max_memory_used_mb {
+            let name = format!("{memory_size}.{MAX_MEMORY_USED_COUNTER}");
+            table
+                .raise(STATS_EPOCH, &name, local.max_memory_used_mb as f64)
+                .await?;
+            stored.max_memory_used_mb = local.max_memory_used_mb;
+        }
+    }
+
+    Ok(stats)
+}
+
+async fn load_parameter(client: &SsmClient, parameter_name: &str) -> Result<FunctionStats> {
+    let response = client.get_parameter().name(parameter_name).send().await;
+
+    let value = match response {
+        Ok(response) => response.parameter.and_then(|parameter| parameter.value),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(|e| e.is_parameter_not_found()) =>
+        {
+            None
+        }
+        Err(e) => return Err(eyre!("Failed to get the stats parameter: {:?}", e)),
+    };
+
+    match value {
+        Some(value) => serde_json::from_str(&value).wrap_err("Failed to parse the stats"),
+        None => Ok(FunctionStats::default()),
+    }
+}
+
+async fn save_parameter(
+    client: &SsmClient,
+    parameter_name: &str,
+    stats: &FunctionStats,
+) -> Result<()> {
+    client
+        .put_parameter()
+        .name(parameter_name)
+        .value(serde_json::to_string(stats)?)
+        .r#type(ParameterType::String)
+        .overwrite(true)
+        .send()
+        .await
+        .map_err(|e| eyre!("Failed to update the stats parameter: {:?}", e))?;
+
+    Ok(())
+}
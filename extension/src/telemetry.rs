@@ -1,93 +1,774 @@
+use crate::backend::{BackendClient, BackendError};
 use crate::environment::LambdaEnvironment;
-use lambda_extension::{LambdaTelemetry, LambdaTelemetryRecord};
-use serde::Serialize;
-use tracing::{error, info};
+use crate::spool::Spool;
+use crate::uploader::Uploader;
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use lambda_extension::{
+    InitPhase, InitType, LambdaTelemetry, LambdaTelemetryRecord, Span, Status, TraceContext,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
-// API_URL contains the backend URL without a trailing slash
-const BASE_API_URL: &str = env!("METRICS_API_URL");
+/// Maximum number of invocations waiting for their `platform.report` record
+const MAX_PENDING_INVOCATIONS: usize = 1000;
 
-#[derive(Clone, Debug, Serialize)]
+/// Default buffering timeout of the Telemetry API, the records buffered at shutdown
+/// are delivered within it
+const TELEMETRY_DRAIN_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Interval of checking whether the last reports were delivered at shutdown
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Metrics {
+pub struct Metrics {
     /// Request identifier
-    request_id: String,
+    pub request_id: String,
     /// Duration in milliseconds
-    duration_ms: f64,
+    pub duration_ms: f64,
     /// Billed duration in milliseconds
-    billed_duration_ms: u64,
+    pub billed_duration_ms: u64,
     /// Memory allocated in megabytes
     #[serde(rename = "memorySizeMB")]
-    memory_size_mb: u64,
+    pub memory_size_mb: u64,
     /// Maximum memory used for the invoke in megabytes
     #[serde(rename = "maxMemoryUsedMB")]
-    max_memory_used_mb: u64,
+    pub max_memory_used_mb: u64,
     /// Init duration in case of a cold start
     #[serde(default = "Option::default")]
-    init_duration_ms: Option<f64>,
+    pub init_duration_ms: Option<f64>,
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub restore_duration_ms: Option<f64>,
+    /// Status of the invoke phase: success, error, failure or timeout
+    pub status: Status,
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    /// Status reported by the runtime when it completed the invocation
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub runtime_done_status: Option<Status>,
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub runtime_done_error_type: Option<String>,
+    /// Time the runtime spent on the invocation, without the extensions overhead
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub runtime_duration_ms: Option<f64>,
+    /// Size of the response produced by the runtime
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub produced_bytes: Option<u64>,
+    /// Version of the function that handled the invocation
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Spans of the invocation, like responseLatency and responseDuration
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<Span>,
+    /// X-Ray tracing context of the invocation
     #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
-    restore_duration_ms: Option<f64>,
+    pub tracing: Option<TraceContext>,
+    /// Timestamp in microseconds when the log was created
+    pub timestamp_us: String,
+    /// Timestamp in microseconds when the log was received by the extension
+    pub received_at_us: String,
+}
+
+/// Metrics of the execution environment initialization
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitMetrics {
+    /// on-demand, provisioned-concurrency or snap-start
+    initialization_type: InitType,
+    /// `init` for a regular init, `invoke` for an init suppressed until the first invocation
+    phase: InitPhase,
+    /// Status reported by the runtime when it completed the initialization
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    error_type: Option<String>,
+    /// Duration of the init phase in milliseconds
+    duration_ms: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spans: Vec<Span>,
     /// Timestamp in microseconds when the log was created
     timestamp_us: String,
+    /// Timestamp in microseconds when the log was received by the extension
+    received_at_us: String,
+}
+
+/// Metrics of a SnapStart execution environment restored from a snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreMetrics {
+    /// Status reported with `platform.restoreReport`
+    status: Status,
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    error_type: Option<String>,
+    /// Status reported by the runtime when it completed the restore hooks
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    runtime_done_status: Option<Status>,
+    /// Duration of the restore phase in milliseconds
+    duration_ms: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spans: Vec<Span>,
+    /// Timestamp in microseconds when the log was created
+    timestamp_us: String,
+    /// Timestamp in microseconds when the log was received by the extension
+    received_at_us: String,
+}
+
+/// Records sent to the backend in a single request
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Batch {
+    pub metrics: Vec<Metrics>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inits: Vec<InitMetrics>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restores: Vec<RestoreMetrics>,
+}
+
+impl Batch {
+    pub fn len(&self) -> usize {
+        self.metrics.len() + self.inits.len() + self.restores.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits the batch into chunks with at most `size` records of every kind
+    fn into_chunks(self, size: usize) -> Vec<Batch> {
+        let mut metrics = self.metrics.chunks(size);
+        let mut inits = self.inits.chunks(size);
+        let mut restores = self.restores.chunks(size);
+        let mut chunks = vec![];
+
+        loop {
+            let chunk = Batch {
+                metrics: metrics.next().map(<[_]>::to_vec).unwrap_or_default(),
+                inits: inits.next().map(<[_]>::to_vec).unwrap_or_default(),
+                restores: restores.next().map(<[_]>::to_vec).unwrap_or_default(),
+            };
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            chunks.push(chunk);
+        }
+
+        chunks
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct RequestData {
-    metrics: Vec<Metrics>,
-    meta: LambdaEnvironment,
+struct RequestData<'a> {
+    #[serde(flatten)]
+    batch: &'a Batch,
+    meta: &'a LambdaEnvironment,
+}
+
+/// Records of an invocation received before its `platform.report`
+#[derive(Debug)]
+struct PendingInvocation {
+    received_at: DateTime<Utc>,
+    version: Option<String>,
+    tracing: Option<TraceContext>,
+    runtime_done: Option<RuntimeDone>,
+}
+
+#[derive(Debug)]
+struct RuntimeDone {
+    status: Status,
+    error_type: Option<String>,
+    duration_ms: Option<f64>,
+    produced_bytes: Option<u64>,
+    spans: Vec<Span>,
+}
+
+/// Status of the init or restore phase reported with `platform.initRuntimeDone`
+/// or `platform.restoreRuntimeDone`
+#[derive(Debug)]
+struct InitRuntimeDone {
+    status: Status,
+    error_type: Option<String>,
+    spans: Vec<Span>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    invocations: HashMap<String, PendingInvocation>,
+    init: Option<InitRuntimeDone>,
+    restore: Option<InitRuntimeDone>,
+}
+
+/// Record delivered by the Telemetry API
+#[derive(Debug)]
+pub enum TelemetryRecord {
+    Lambda(LambdaTelemetry),
+    /// SnapStart restore records, not modelled by lambda-extension
+    Restore(RestoreTelemetry),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreTelemetry {
+    time: DateTime<Utc>,
+    #[serde(flatten)]
+    record: RestoreRecord,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "record")]
+enum RestoreRecord {
+    #[serde(rename = "platform.restoreStart")]
+    Start {},
+    #[serde(rename = "platform.restoreRuntimeDone", rename_all = "camelCase")]
+    RuntimeDone {
+        status: Status,
+        error_type: Option<String>,
+        #[serde(default)]
+        spans: Vec<Span>,
+    },
+    #[serde(rename = "platform.restoreReport", rename_all = "camelCase")]
+    Report {
+        status: Status,
+        error_type: Option<String>,
+        metrics: RestoreReportMetrics,
+        #[serde(default)]
+        spans: Vec<Span>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RestoreReportMetrics {
+    duration_ms: f64,
+}
+
+/// Parses the records of a Telemetry API request one by one, so a record of an unknown type
+/// doesn't make the whole batch lost. The unknown records are skipped.
+pub fn parse_records(body: &[u8]) -> serde_json::Result<Vec<TelemetryRecord>> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body)?;
+    let mut records = Vec::with_capacity(values.len());
+
+    for value in values {
+        let record = match LambdaTelemetry::deserialize(&value) {
+            Ok(log) => TelemetryRecord::Lambda(log),
+            Err(e) => match RestoreTelemetry::deserialize(&value) {
+                Ok(log) => TelemetryRecord::Restore(log),
+                Err(_) => {
+                    warn!("Skipping an unknown telemetry record: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Correlates records of the same invocation, since the Telemetry API may deliver them
+/// in different batches
+#[derive(Clone, Debug, Default)]
+pub struct InvocationTracker {
+    state: Arc<Mutex<TrackerState>>,
+}
+
+impl InvocationTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of invocations still waiting for their `platform.report` record
+    pub fn pending_invocations(&self) -> usize {
+        self.state
+            .lock()
+            .map(|state| state.invocations.len())
+            .unwrap_or_default()
+    }
+
+    /// Converts the telemetry records into a batch of metrics
+    fn process(&self, logs: Vec<TelemetryRecord>) -> Batch {
+        let mut batch = Batch::default();
+        let received_at_us = Utc::now().timestamp_micros().to_string();
+
+        let Ok(mut state) = self.state.lock() else {
+            error!("Failed to lock the invocation tracker");
+            return batch;
+        };
+
+        for log in logs {
+            let log = match log {
+                TelemetryRecord::Lambda(log) => log,
+                TelemetryRecord::Restore(log) => {
+                    state.process_restore(log, &received_at_us, &mut batch);
+                    continue;
+                }
+            };
+
+            match log.record {
+                LambdaTelemetryRecord::PlatformStart {
+                    request_id,
+                    version,
+                    tracing,
+                } => {
+                    let invocation = state.pending(request_id);
+                    invocation.version = version;
+                    invocation.tracing = tracing;
+                }
+
+                LambdaTelemetryRecord::PlatformRuntimeDone {
+                    request_id,
+                    status,
+                    error_type,
+                    metrics,
+                    spans,
+                    tracing,
+                } => {
+                    let invocation = state.pending(request_id);
+                    invocation.tracing = invocation.tracing.take().or(tracing);
+                    invocation.runtime_done = Some(RuntimeDone {
+                        status,
+                        error_type,
+                        duration_ms: metrics.as_ref().map(|metrics| metrics.duration_ms),
+                        produced_bytes: metrics.and_then(|metrics| metrics.produced_bytes),
+                        spans,
+                    });
+                }
+
+                LambdaTelemetryRecord::PlatformReport {
+                    request_id,
+                    status,
+                    error_type,
+                    metrics,
+                    mut spans,
+                    tracing,
+                } => {
+                    let invocation = state.invocations.remove(&request_id);
+                    let (version, pending_tracing, runtime_done) = match invocation {
+                        Some(invocation) => (
+                            invocation.version,
+                            invocation.tracing,
+                            invocation.runtime_done,
+                        ),
+                        None => (None, None, None),
+                    };
+
+                    if let Some(runtime_done) = &runtime_done {
+                        spans.extend(runtime_done.spans.iter().cloned());
+                    }
+
+                    batch.metrics.push(Metrics {
+                        request_id,
+                        duration_ms: metrics.duration_ms,
+                        billed_duration_ms: metrics.billed_duration_ms,
+                        memory_size_mb: metrics.memory_size_mb,
+                        max_memory_used_mb: metrics.max_memory_used_mb,
+                        init_duration_ms: metrics.init_duration_ms,
+                        restore_duration_ms: metrics.restore_duration_ms,
+                        status,
+                        error_type,
+                        runtime_done_status: runtime_done.as_ref().map(|done| done.status.clone()),
+                        runtime_done_error_type: runtime_done
+                            .as_ref()
+                            .and_then(|done| done.error_type.clone()),
+                        runtime_duration_ms: runtime_done
+                            .as_ref()
+                            .and_then(|done| done.duration_ms),
+                        produced_bytes: runtime_done.as_ref().and_then(|done| done.produced_bytes),
+                        version,
+                        spans,
+                        tracing: tracing.or(pending_tracing),
+                        timestamp_us: log.time.timestamp_micros().to_string(),
+                        received_at_us: received_at_us.clone(),
+                    })
+                }
+
+                LambdaTelemetryRecord::PlatformInitRuntimeDone {
+                    status,
+                    error_type,
+                    spans,
+                    ..
+                } => {
+                    state.init = Some(InitRuntimeDone {
+                        status,
+                        error_type,
+                        spans,
+                    });
+                }
+
+                LambdaTelemetryRecord::PlatformInitReport {
+                    initialization_type,
+                    phase,
+                    metrics,
+                    mut spans,
+                } => {
+                    let init = state.init.take();
+
+                    if let Some(init) = &init {
+                        spans.extend(init.spans.iter().cloned());
+                    }
+
+                    batch.inits.push(InitMetrics {
+                        initialization_type,
+                        phase,
+                        status: init.as_ref().map(|init| init.status.clone()),
+                        error_type: init.and_then(|init| init.error_type),
+                        duration_ms: metrics.duration_ms,
+                        spans,
+                        timestamp_us: log.time.timestamp_micros().to_string(),
+                        received_at_us: received_at_us.clone(),
+                    })
+                }
+
+                _ => {}
+            }
+        }
+
+        batch
+    }
+}
+
+impl TrackerState {
+    fn process_restore(&mut self, log: RestoreTelemetry, received_at_us: &str, batch: &mut Batch) {
+        match log.record {
+            RestoreRecord::Start {} => {}
+
+            RestoreRecord::RuntimeDone {
+                status,
+                error_type,
+                spans,
+            } => {
+                self.restore = Some(InitRuntimeDone {
+                    status,
+                    error_type,
+                    spans,
+                });
+            }
+
+            RestoreRecord::Report {
+                status,
+                error_type,
+                metrics,
+                mut spans,
+            } => {
+                let restore = self.restore.take();
+
+                if let Some(restore) = &restore {
+                    spans.extend(restore.spans.iter().cloned());
+                }
+
+                batch.restores.push(RestoreMetrics {
+                    status,
+                    error_type,
+                    runtime_done_status: restore.map(|restore| restore.status),
+                    duration_ms: metrics.duration_ms,
+                    spans,
+                    timestamp_us: log.time.timestamp_micros().to_string(),
+                    received_at_us: received_at_us.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Returns the pending records of the invocation, evicting the oldest one if the limit is reached
+    fn pending(&mut self, request_id: String) -> &mut PendingInvocation {
+        if !self.invocations.contains_key(&request_id)
+            && self.invocations.len() >= MAX_PENDING_INVOCATIONS
+        {
+            let oldest = self
+                .invocations
+                .iter()
+                .min_by_key(|(_, invocation)| invocation.received_at)
+                .map(|(request_id, _)| request_id.clone());
+
+            if let Some(oldest) = oldest {
+                warn!(
+                    "Dropping records of the invocation {} without a report",
+                    oldest
+                );
+                self.invocations.remove(&oldest);
+            }
+        }
+
+        self.invocations
+            .entry(request_id)
+            .or_insert_with(|| PendingInvocation {
+                received_at: Utc::now(),
+                version: None,
+                tracing: None,
+                runtime_done: None,
+            })
+    }
+}
+
+/// State shared between the telemetry batches
+#[derive(Clone, Debug)]
+pub struct TelemetryContext {
+    /// Sends the metrics to the telemetry sinks in the background
+    pub uploader: Uploader,
+
+    /// Records of an invocation may be delivered in different batches
+    pub tracker: InvocationTracker,
+
+    /// Forwards the metrics to the updater task
+    pub observations: mpsc::Sender<Vec<Metrics>>,
 }
 
 pub async fn telemetry_handler(
-    environment: LambdaEnvironment,
-    logs: Vec<LambdaTelemetry>,
+    context: TelemetryContext,
+    logs: Vec<TelemetryRecord>,
 ) -> eyre::Result<()> {
-    let client = reqwest::Client::new();
+    let TelemetryContext {
+        uploader,
+        tracker,
+        observations,
+    } = context;
 
-    let mut batch: Vec<Metrics> = vec![];
     info!("Processing {} logs", logs.len());
+    let batch = tracker.process(logs);
 
-    for log in logs {
-        if let LambdaTelemetryRecord::PlatformReport {
-            request_id,
-            metrics,
-            ..
-        } = log.record
-        {
-            batch.push(Metrics {
-                request_id,
-                duration_ms: metrics.duration_ms,
-                billed_duration_ms: metrics.billed_duration_ms,
-                memory_size_mb: metrics.memory_size_mb,
-                max_memory_used_mb: metrics.max_memory_used_mb,
-                init_duration_ms: metrics.init_duration_ms,
-                restore_duration_ms: metrics.restore_duration_ms,
-                timestamp_us: chrono::Utc::now().timestamp_micros().to_string(),
-            })
+    // The updater must never slow down the telemetry processing, so the metrics are dropped
+    // if it can't keep up
+    if !batch.metrics.is_empty() {
+        if let Err(e) = observations.try_send(batch.metrics.clone()) {
+            warn!("Failed to forward metrics to the updater: {}", e);
         }
     }
 
-    let api_url = format!("{BASE_API_URL}/collect");
-    info!("Sending metrics ({}) to {}", batch.len(), api_url);
-
-    // TODO Split the batch into 50-100 records per chunk
-    let result = client
-        .post(api_url)
-        .timeout(std::time::Duration::from_secs(10))
-        .header(
-            "Authorization",
-            format!("Bearer {}", environment.access_token),
-        )
-        .json(&RequestData {
-            metrics: batch,
-            meta: environment.clone(),
-        })
-        .send()
-        .await;
+    if !uploader.is_enabled() {
+        return Ok(());
+    }
 
-    match result {
-        Ok(_) => info!("Metrics sent successfully"),
-        Err(e) => error!("Failed to send metrics: {}", e),
+    if batch.is_empty() {
+        info!("No metrics to send");
+    } else {
+        uploader.enqueue(batch);
     }
 
     Ok(())
 }
+
+/// Waits for the records still buffered by the Telemetry API, then sends the queued metrics
+/// and flushes the sinks. Logs what is dropped if the deadline is reached first.
+pub async fn shutdown_telemetry(context: &TelemetryContext, deadline: Instant) {
+    if !context.uploader.is_enabled() {
+        return;
+    }
+
+    // The last reports arrive within the buffering timeout of the Telemetry API
+    let drain_until = deadline.min(Instant::now() + TELEMETRY_DRAIN_TIMEOUT);
+
+    while context.tracker.pending_invocations() > 0 && Instant::now() < drain_until {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    if tokio::time::timeout_at(deadline, context.uploader.shutdown())
+        .await
+        .is_err()
+    {
+        let (batches, records) = context.uploader.queued();
+
+        warn!(
+            dropped_batches = batches,
+            dropped_records = records,
+            unreported_invocations = context.tracker.pending_invocations(),
+            "Shutdown deadline reached before the telemetry was uploaded"
+        );
+    }
+}
+
+/// Sends the batch in chunks and spools the chunks that may be accepted later.
+/// Returns `true` if all the chunks were sent successfully.
+pub async fn upload(
+    backend: &BackendClient,
+    environment: &LambdaEnvironment,
+    spool: &Spool,
+    batch: Batch,
+) -> bool {
+    let records = batch.len();
+    let chunks = batch.into_chunks(environment.telemetry.chunk_size);
+    let total = chunks.len();
+
+    info!(
+        "Sending metrics ({}) in {} chunk(s) to {}",
+        records,
+        total,
+        backend.url("/collect")
+    );
+
+    // Every chunk is sent independently, so a failed one doesn't affect the rest of the batch
+    let mut requests = Vec::with_capacity(total);
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let request = send_chunk(backend, environment, chunk);
+        requests.push(async move { (index, chunk, request.await) });
+    }
+
+    let mut results =
+        stream::iter(requests).buffer_unordered(environment.telemetry.max_concurrency);
+
+    let mut failed = 0;
+    let mut retryable = vec![];
+
+    while let Some((index, chunk, result)) = results.next().await {
+        match result {
+            Ok(_) => info!("Metrics chunk {}/{} sent successfully", index + 1, total),
+            Err(e) => {
+                error!(
+                    "Failed to send metrics chunk {}/{}: {}",
+                    index + 1,
+                    total,
+                    e
+                );
+
+                failed += 1;
+
+                // Rejected chunks would be rejected again, so only keep the ones worth retrying
+                if e.is_retryable() {
+                    retryable.push(chunk);
+                }
+            }
+        }
+    }
+
+    if failed == 0 {
+        return true;
+    }
+
+    error!("Failed to send {} of {} metrics chunk(s)", failed, total);
+
+    if spool.is_enabled() && !retryable.is_empty() {
+        let spool = spool.lock().await;
+
+        for chunk in retryable {
+            if let Err(e) = spool.push(chunk).await {
+                error!("Failed to spool {} metrics: {:?}", chunk.len(), e);
+            }
+        }
+    }
+
+    false
+}
+
+/// Sends the spooled batches the oldest first until one fails or the limit is reached
+pub async fn replay_spool(
+    backend: &BackendClient,
+    environment: &LambdaEnvironment,
+    spool: &Spool,
+    limit: Option<usize>,
+) {
+    if !spool.is_enabled() {
+        return;
+    }
+
+    let spool = spool.lock().await;
+
+    let entries = match spool.entries().await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to list the spool: {:?}", e);
+            return;
+        }
+    };
+
+    if entries.is_empty() {
+        return;
+    }
+
+    let limit = limit.unwrap_or(entries.len());
+    info!(
+        "Replaying {} of {} spooled batch(es)",
+        limit.min(entries.len()),
+        entries.len()
+    );
+
+    for (path, _) in entries.into_iter().take(limit) {
+        let batch: Batch = match spool.read(&path).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                // A corrupted file would block the spool forever, so drop it
+                warn!("Dropping unreadable spool file {}: {:?}", path.display(), e);
+                let _ = spool.remove(&path).await;
+                continue;
+            }
+        };
+
+        match send_chunk(backend, environment, &batch).await {
+            Ok(_) => info!("Replayed {} spooled metrics", batch.len()),
+
+            // The batch is dropped below, otherwise it would block the spool forever
+            Err(e @ BackendError::Permanent { .. }) => {
+                error!("Dropping spooled metrics rejected by the backend: {}", e)
+            }
+
+            Err(e) => {
+                error!("Failed to replay spooled metrics: {}", e);
+                break;
+            }
+        }
+
+        if let Err(e) = spool.remove(&path).await {
+            error!("Failed to remove the replayed spool file: {:?}", e);
+        }
+    }
+}
+
+/// Sends a single chunk of metrics to the backend
+async fn send_chunk(
+    backend: &BackendClient,
+    environment: &LambdaEnvironment,
+    chunk: &Batch,
+) -> Result<(), BackendError> {
+    backend
+        .post(
+            "/collect",
+            &RequestData {
+                batch: chunk,
+                meta: environment,
+            },
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_restore_and_skips_unknown_records() {
+        let body = r#"[
+            {"time": "2024-01-01T00:00:00.000Z", "type": "platform.restoreStart",
+             "record": {"runtimeVersion": "java21", "functionName": "f", "functionVersion": "1"}},
+            {"time": "2024-01-01T00:00:00.100Z", "type": "platform.restoreRuntimeDone",
+             "record": {"status": "success"}},
+            {"time": "2024-01-01T00:00:00.200Z", "type": "platform.restoreReport",
+             "record": {"status": "success", "metrics": {"durationMs": 180.5}}},
+            {"time": "2024-01-01T00:00:00.300Z", "type": "platform.somethingNew",
+             "record": {}},
+            {"time": "2024-01-01T00:00:00.400Z", "type": "platform.start",
+             "record": {"requestId": "a", "version": "1"}},
+            {"time": "2024-01-01T00:00:00.500Z", "type": "platform.report",
+             "record": {"requestId": "a", "status": "success", "metrics": {
+                "durationMs": 12.5, "billedDurationMs": 13, "memorySizeMB": 512,
+                "maxMemoryUsedMB": 128}}}
+        ]"#;
+
+        let records = parse_records(body.as_bytes()).unwrap();
+        assert_eq!(records.len(), 5);
+
+        let batch = InvocationTracker::new().process(records);
+
+        assert_eq!(batch.restores.len(), 1);
+        assert_eq!(batch.restores[0].duration_ms, 180.5);
+        assert_eq!(batch.restores[0].runtime_done_status, Some(Status::Success));
+        assert_eq!(batch.metrics.len(), 1);
+        assert_eq!(batch.metrics[0].version.as_deref(), Some("1"));
+    }
+}
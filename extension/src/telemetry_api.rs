@@ -0,0 +1,128 @@
+use crate::startup::Startup;
+use crate::telemetry::{parse_records, telemetry_handler};
+use eyre::{eyre, Context};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tracing::error;
+
+/// Port the Telemetry API delivers the records to
+const TELEMETRY_PORT: u16 = 9003;
+
+/// First schema with the SnapStart restore records
+const SCHEMA_VERSION: &str = "2022-12-13";
+
+/// Receives the records of the Telemetry API.
+///
+/// lambda-extension rejects the whole request if it has a record it doesn't model, e.g. the
+/// restore records of SnapStart, so the records are received and parsed here instead.
+pub struct TelemetryListener {
+    listener: TcpListener,
+}
+
+impl TelemetryListener {
+    /// Binds the port, must be done before subscribing
+    pub async fn bind() -> eyre::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], TELEMETRY_PORT)))
+            .await
+            .wrap_err("Failed to bind the telemetry listener")?;
+
+        Ok(Self { listener })
+    }
+
+    /// Subscribes to the platform records, must be done during the init phase
+    pub async fn subscribe(&self, extension_id: &str) -> eyre::Result<()> {
+        let runtime_api =
+            env::var("AWS_LAMBDA_RUNTIME_API").wrap_err("AWS_LAMBDA_RUNTIME_API is not set")?;
+
+        let subscription = json!({
+            "schemaVersion": SCHEMA_VERSION,
+            "types": ["platform"],
+            "buffering": {
+                "timeoutMs": 1000,
+                "maxBytes": 262144,
+                "maxItems": 10000,
+            },
+            "destination": {
+                "protocol": "HTTP",
+                "URI": format!("http://sandbox.localdomain:{TELEMETRY_PORT}"),
+            },
+        });
+
+        reqwest::Client::new()
+            .put(format!("http://{runtime_api}/2022-07-01/telemetry"))
+            .header("Lambda-Extension-Identifier", extension_id)
+            .json(&subscription)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| eyre!("Failed to subscribe to the Telemetry API: {:?}", e))?;
+
+        Ok(())
+    }
+
+    /// Processes the records in the background, they are dropped in the degraded mode
+    pub fn serve(self, startup: Startup) {
+        tokio::spawn(async move {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("Failed to accept a telemetry connection: {:?}", e);
+                        continue;
+                    }
+                };
+
+                let startup = startup.clone();
+                let service = service_fn(move |request| handle(startup.clone(), request));
+
+                tokio::spawn(async move {
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        error!("Failed to serve a telemetry connection: {:?}", e);
+                    }
+                });
+            }
+        });
+    }
+}
+
+async fn handle(
+    startup: Startup,
+    request: Request<Incoming>,
+) -> Result<Response<Empty<Bytes>>, Infallible> {
+    let status = match request.into_body().collect().await {
+        Ok(body) => match parse_records(&body.to_bytes()) {
+            Ok(records) => {
+                if let Some(services) = startup.services() {
+                    if let Err(e) = telemetry_handler(services.telemetry.clone(), records).await {
+                        error!("Failed to process the telemetry: {:?}", e);
+                    }
+                }
+                StatusCode::OK
+            }
+            Err(e) => {
+                error!("Failed to parse the telemetry: {:?}", e);
+                StatusCode::BAD_REQUEST
+            }
+        },
+        Err(e) => {
+            error!("Failed to read the telemetry: {:?}", e);
+            StatusCode::BAD_REQUEST
+        }
+    };
+
+    let mut response = Response::new(Empty::new());
+    *response.status_mut() = status;
+    Ok(response)
+}
@@ -0,0 +1,282 @@
+use crate::environment::ThrottleConfig;
+use aws_sdk_ssm::types::ParameterType;
+use aws_sdk_ssm::Client as SsmClient;
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Formatter;
+use tracing::error;
+
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// Memory changes applied to the function during the last day
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeHistory {
+    /// Unix timestamps in seconds of the applied changes, oldest first
+    pub applied_at: Vec<i64>,
+}
+
+impl ChangeHistory {
+    /// Adds a change and forgets the ones older than a day
+    pub fn record(&mut self, now: i64) {
+        self.applied_at
+            .retain(|applied_at| now - applied_at < DAY_SECONDS);
+        self.applied_at.push(now);
+    }
+}
+
+/// Reason a recommended memory change is not applied
+#[derive(Clone, Debug, PartialEq)]
+pub enum Suppression {
+    /// The recommendation hasn't been the same for enough consecutive polls
+    Unstable { polls: u32, required: u32 },
+    /// The last change was applied too recently
+    Cooldown { remaining_seconds: i64 },
+    /// The function was changed too many times during the last day
+    DailyBudget { changes: usize, max_changes: u32 },
+    /// The change history can't be loaded, so the limits can't be checked
+    Unknown,
+}
+
+impl fmt::Display for Suppression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Suppression::Unstable { polls, required } => write!(
+                f,
+                "recommended by {polls} of {required} required consecutive polls"
+            ),
+            Suppression::Cooldown { remaining_seconds } => write!(
+                f,
+                "cooldown after the last change ends in {remaining_seconds} seconds"
+            ),
+            Suppression::DailyBudget {
+                changes,
+                max_changes,
+            } => write!(
+                f,
+                "{changes} of {max_changes} allowed changes were made during the last day"
+            ),
+            Suppression::Unknown => write!(f, "change history is unavailable"),
+        }
+    }
+}
+
+/// Checks the cooldown and the daily budget against the applied changes
+pub fn evaluate(
+    config: &ThrottleConfig,
+    history: &ChangeHistory,
+    now: i64,
+) -> Result<(), Suppression> {
+    let recent: Vec<i64> = history
+        .applied_at
+        .iter()
+        .copied()
+        .filter(|applied_at| now - applied_at < DAY_SECONDS)
+        .collect();
+
+    if let Some(last) = recent.iter().max() {
+        let remaining_seconds = last + config.cooldown.as_secs() as i64 - now;
+
+        if remaining_seconds > 0 {
+            return Err(Suppression::Cooldown { remaining_seconds });
+        }
+    }
+
+    if config.max_changes_per_day > 0 && recent.len() >= config.max_changes_per_day as usize {
+        return Err(Suppression::DailyBudget {
+            changes: recent.len(),
+            max_changes: config.max_changes_per_day,
+        });
+    }
+
+    Ok(())
+}
+
+/// Keeps the change history in an SSM parameter shared by the execution environments
+#[derive(Clone, Debug)]
+pub struct ChangeStore {
+    client: SsmClient,
+    parameter_name: String,
+}
+
+impl ChangeStore {
+    pub fn new(client: SsmClient, parameter_name: String) -> Self {
+        Self {
+            client,
+            parameter_name,
+        }
+    }
+
+    /// Loads the history, a missing parameter is treated as no changes
+    pub async fn load(&self) -> Result<ChangeHistory> {
+        let response = self
+            .client
+            .get_parameter()
+            .name(&self.parameter_name)
+            .send()
+            .await;
+
+        let value = match response {
+            Ok(response) => response.parameter.and_then(|parameter| parameter.value),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_parameter_not_found()) =>
+            {
+                None
+            }
+            Err(e) => return Err(eyre!("Failed to get the change history parameter: {:?}", e)),
+        };
+
+        match value {
+            Some(value) => {
+                serde_json::from_str(&value).wrap_err("Failed to parse the change history")
+            }
+            None => Ok(ChangeHistory::default()),
+        }
+    }
+
+    pub async fn save(&self, history: &ChangeHistory) -> Result<()> {
+        self.client
+            .put_parameter()
+            .name(&self.parameter_name)
+            .value(serde_json::to_string(history)?)
+            .r#type(ParameterType::String)
+            .overwrite(true)
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to update the change history parameter: {:?}", e))?;
+
+        Ok(())
+    }
+
+    /// Adds an applied change to the history, failures are only logged
+    pub async fn record(&self) {
+        let now = chrono::Utc::now().timestamp();
+
+        let result = match self.load().await {
+            Ok(mut history) => {
+                history.record(now);
+                self.save(&history).await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            error!("Failed to record the memory change: {:?}", e);
+        }
+    }
+}
+
+/// Counts the consecutive polls of this execution environment recommending the same memory size
+#[derive(Clone, Debug, Default)]
+pub struct Stability {
+    memory_size_mb: Option<i32>,
+    polls: u32,
+}
+
+impl Stability {
+    /// Adds a poll recommending the memory size, returns the number of consecutive ones
+    pub fn observe(&mut self, memory_size_mb: i32) -> u32 {
+        if self.memory_size_mb == Some(memory_size_mb) {
+            self.polls += 1;
+        } else {
+            self.memory_size_mb = Some(memory_size_mb);
+            self.polls = 1;
+        }
+
+        self.polls
+    }
+
+    /// Starts counting again, e.g. after a poll without a change
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn config(cooldown_seconds: u64, max_changes_per_day: u32) -> ThrottleConfig {
+        ThrottleConfig {
+            cooldown: Duration::from_secs(cooldown_seconds),
+            max_changes_per_day,
+            stable_polls: 1,
+            parameter_name: "/optimeist/test/changes".to_string(),
+        }
+    }
+
+    fn history(ages_seconds: &[i64]) -> ChangeHistory {
+        ChangeHistory {
+            applied_at: ages_seconds.iter().map(|age| NOW - age).collect(),
+        }
+    }
+
+    #[test]
+    fn allows_without_changes() {
+        assert_eq!(evaluate(&config(3600, 6), &history(&[]), NOW), Ok(()));
+    }
+
+    #[test]
+    fn suppresses_during_cooldown() {
+        assert_eq!(
+            evaluate(&config(3600, 0), &history(&[7200, 600]), NOW),
+            Err(Suppression::Cooldown {
+                remaining_seconds: 3000
+            })
+        );
+    }
+
+    #[test]
+    fn allows_after_cooldown() {
+        assert_eq!(evaluate(&config(3600, 0), &history(&[3600]), NOW), Ok(()));
+    }
+
+    #[test]
+    fn suppresses_when_budget_is_used() {
+        assert_eq!(
+            evaluate(&config(0, 2), &history(&[7200, 3600]), NOW),
+            Err(Suppression::DailyBudget {
+                changes: 2,
+                max_changes: 2
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_changes_older_than_a_day() {
+        assert_eq!(
+            evaluate(&config(0, 2), &history(&[DAY_SECONDS + 1, 3600]), NOW),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn disabled_limits_allow_every_change() {
+        assert_eq!(evaluate(&config(0, 0), &history(&[0, 0, 0]), NOW), Ok(()));
+    }
+
+    #[test]
+    fn record_forgets_old_changes() {
+        let mut history = history(&[DAY_SECONDS, 60]);
+        history.record(NOW);
+
+        assert_eq!(history.applied_at, vec![NOW - 60, NOW]);
+    }
+
+    #[test]
+    fn stability_counts_consecutive_polls() {
+        let mut stability = Stability::default();
+
+        assert_eq!(stability.observe(512), 1);
+        assert_eq!(stability.observe(512), 2);
+        assert_eq!(stability.observe(1024), 1);
+
+        stability.reset();
+        assert_eq!(stability.observe(1024), 1);
+    }
+}
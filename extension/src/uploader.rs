@@ -0,0 +1,170 @@
+use crate::environment::TelemetryConfig;
+use crate::sink::TelemetrySink;
+use crate::telemetry::Batch;
+use futures::future::join_all;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Defines which batches are dropped once the upload queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// Remove the oldest queued batch to make room for the new one
+    #[default]
+    DropOldest,
+    /// Keep the queued batches and drop the new one
+    DropNewest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "DROP_OLDEST" => Ok(OverflowPolicy::DropOldest),
+            "DROP_NEWEST" => Ok(OverflowPolicy::DropNewest),
+            _ => Err(format!("Unknown upload queue overflow policy: {s}")),
+        }
+    }
+}
+
+/// Bounded queue of the batches waiting for the upload
+#[derive(Debug)]
+struct UploadQueue {
+    batches: Mutex<VecDeque<Batch>>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    /// Wakes the uploader task up when a batch is queued or the queue is closed
+    notify: Notify,
+    closed: AtomicBool,
+    /// Number of batches dropped because the queue was full
+    dropped: AtomicU64,
+}
+
+impl UploadQueue {
+    fn pop(&self) -> Option<Batch> {
+        self.batches.lock().ok()?.pop_front()
+    }
+}
+
+/// Sends the telemetry batches to the sinks in a background task,
+/// so a slow sink never holds the Telemetry API handler
+#[derive(Clone, Debug)]
+pub struct Uploader {
+    queue: Arc<UploadQueue>,
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Nothing is queued without sinks
+    enabled: bool,
+}
+
+impl Uploader {
+    pub fn new(config: &TelemetryConfig, sinks: Vec<Box<dyn TelemetrySink>>) -> Self {
+        let queue = Arc::new(UploadQueue {
+            batches: Mutex::new(VecDeque::new()),
+            capacity: config.queue_capacity,
+            overflow: config.queue_overflow,
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+        });
+
+        let enabled = !sinks.is_empty();
+        let handle = tokio::spawn(upload_task(queue.clone(), sinks));
+
+        Self {
+            queue,
+            handle: Arc::new(Mutex::new(Some(handle))),
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Queues the batch without waiting for the upload, the overflow policy decides
+    /// which batch is dropped if the queue is full
+    pub fn enqueue(&self, batch: Batch) {
+        let queue = &self.queue;
+
+        let depth = {
+            let Ok(mut batches) = queue.batches.lock() else {
+                error!("Failed to lock the upload queue");
+                return;
+            };
+
+            if batches.len() >= queue.capacity {
+                let dropped = match queue.overflow {
+                    OverflowPolicy::DropOldest => {
+                        let oldest = batches.pop_front();
+                        batches.push_back(batch);
+                        oldest
+                    }
+                    OverflowPolicy::DropNewest => Some(batch),
+                };
+
+                let total = queue.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    dropped_records = dropped.map(|batch| batch.len()).unwrap_or_default(),
+                    dropped_batches = total,
+                    "Upload queue is full, dropped a batch"
+                );
+            } else {
+                batches.push_back(batch);
+            }
+
+            batches.len()
+        };
+
+        queue.notify.notify_one();
+
+        info!(
+            queue_depth = depth,
+            dropped_batches = queue.dropped.load(Ordering::Relaxed),
+            "Queued metrics for upload"
+        );
+    }
+
+    /// Returns the number of queued batches and records
+    pub fn queued(&self) -> (usize, usize) {
+        self.queue
+            .batches
+            .lock()
+            .map(|batches| (batches.len(), batches.iter().map(Batch::len).sum()))
+            .unwrap_or_default()
+    }
+
+    /// Sends the queued batches and flushes the sinks, then stops the uploader task
+    pub async fn shutdown(&self) {
+        self.queue.closed.store(true, Ordering::Relaxed);
+        self.queue.notify.notify_one();
+
+        let handle = self.handle.lock().ok().and_then(|mut handle| handle.take());
+
+        if let Some(handle) = handle {
+            if let Err(e) = handle.await {
+                error!("Uploader task failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Sends the queued batches one by one to all the sinks until the queue is closed and empty
+async fn upload_task(queue: Arc<UploadQueue>, sinks: Vec<Box<dyn TelemetrySink>>) {
+    loop {
+        match queue.pop() {
+            Some(batch) => {
+                join_all(sinks.iter().map(|sink| sink.send(&batch))).await;
+            }
+            None if queue.closed.load(Ordering::Relaxed) => break,
+            None => queue.notify.notified().await,
+        }
+    }
+
+    // Everything the sinks keep, like the spool, is sent before the extension shuts down
+    join_all(sinks.iter().map(|sink| sink.flush())).await;
+}
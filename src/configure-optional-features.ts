@@ -0,0 +1,88 @@
+import * as cdk from 'aws-cdk-lib'
+import {OptimeistProps} from './types'
+
+/**
+ * Sets the environment variables of the optional extension features enabled in the props
+ * and grants the Lambda function the permissions they need
+ */
+export const configureOptionalFeatures = (lambdaFunction: cdk.aws_lambda.Function, props: OptimeistProps) => {
+  if (props.leaseTable) {
+    lambdaFunction.addEnvironment('OPTIMEIST_LEASE_TABLE_NAME', props.leaseTable.tableName)
+    props.leaseTable.grant(lambdaFunction, 'dynamodb:GetItem', 'dynamodb:PutItem', 'dynamodb:UpdateItem', 'dynamodb:DeleteItem')
+  }
+
+  if (props.publishAlias) {
+    lambdaFunction.addEnvironment('OPTIMEIST_PUBLISH_ALIAS', props.publishAlias)
+
+    const actions = ['lambda:PublishVersion', 'lambda:UpdateAlias']
+
+    // The canary rollout reads the memory size of the version the alias points to
+    if (props.canaryWeight) {
+      lambdaFunction.addEnvironment('OPTIMEIST_CANARY_WEIGHT', props.canaryWeight.toString())
+      actions.push('lambda:GetAlias', 'lambda:GetFunction')
+    }
+
+    // A separate policy, the role's default policy can't refer to the function it belongs to
+    new cdk.aws_iam.Policy(lambdaFunction, 'OptimeistPublishPolicy', {
+      roles: [lambdaFunction.role!],
+      statements: [
+        new cdk.aws_iam.PolicyStatement({
+          actions,
+          resources: [lambdaFunction.functionArn, `${lambdaFunction.functionArn}:*`],
+        }),
+      ],
+    })
+  }
+
+  if (props.auditSink) {
+    lambdaFunction.addEnvironment('OPTIMEIST_AUDIT_SINK', props.auditSink)
+  }
+
+  if (props.auditSink === 'SSM') {
+    grantParameter(lambdaFunction, 'OptimeistAuditPolicy', 'audit', ['ssm:PutParameter'])
+  }
+
+  if (props.decisionMode) {
+    lambdaFunction.addEnvironment('OPTIMEIST_DECISION_MODE', props.decisionMode)
+  }
+
+  // The local recommender keeps the stats in the lease table if there is one
+  if (props.decisionMode === 'LOCAL' && !props.leaseTable) {
+    grantParameter(lambdaFunction, 'OptimeistStatsPolicy', 'stats', ['ssm:GetParameter', 'ssm:PutParameter'])
+  }
+
+  if (props.updateCooldown) {
+    lambdaFunction.addEnvironment('OPTIMEIST_UPDATE_COOLDOWN_SECONDS', props.updateCooldown.toSeconds().toString())
+  }
+
+  if (props.maxChangesPerDay) {
+    lambdaFunction.addEnvironment('OPTIMEIST_MAX_CHANGES_PER_DAY', props.maxChangesPerDay.toString())
+  }
+
+  // The limits are checked against the changes shared by the execution environments
+  if (props.updateCooldown?.toSeconds() || props.maxChangesPerDay) {
+    grantParameter(lambdaFunction, 'OptimeistChangesPolicy', 'changes', ['ssm:GetParameter', 'ssm:PutParameter'])
+  }
+}
+
+/**
+ * Grants the actions on the `/optimeist/<function name>/<suffix>` parameter.
+ * A separate policy, the role's default policy can't refer to the function it belongs to
+ */
+const grantParameter = (lambdaFunction: cdk.aws_lambda.Function, id: string, suffix: string, actions: string[]) => {
+  new cdk.aws_iam.Policy(lambdaFunction, id, {
+    roles: [lambdaFunction.role!],
+    statements: [
+      new cdk.aws_iam.PolicyStatement({
+        actions,
+        resources: [
+          cdk.Stack.of(lambdaFunction).formatArn({
+            service: 'ssm',
+            resource: 'parameter',
+            resourceName: `optimeist/${lambdaFunction.functionName}/${suffix}`,
+          }),
+        ],
+      }),
+    ],
+  })
+}
@@ -1,6 +1,7 @@
 import * as aws_lambda_go_alpha from '@aws-cdk/aws-lambda-go-alpha'
 import * as cdk from 'aws-cdk-lib'
 import {Construct} from 'constructs'
+import {configureOptionalFeatures} from './configure-optional-features'
 import {getExtensionLayerArn} from './get-extension-layer-arn'
 import {DecisionAlgorithmType, OptimeistProps} from './types'
 
@@ -45,6 +46,11 @@ export class GoFunction extends aws_lambda_go_alpha.GoFunction {
      */
     accessTokenSecret.grantRead(this)
 
+    /**
+     * Configure the optional features and grant the permissions they need
+     */
+    configureOptionalFeatures(this, props.optimeistProps)
+
     /**
      * Grant the Lambda function permission to update its own configuration.
      * Required for update from the lambda layer.
@@ -2,3 +2,4 @@ export * from './go-function'
 export * from './lambda-function'
 export * from './nodejs-function'
 export * from './python-function'
+export * from './types'
@@ -1,5 +1,6 @@
 import * as cdk from 'aws-cdk-lib'
 import {Construct} from 'constructs'
+import {configureOptionalFeatures} from './configure-optional-features'
 import {getExtensionLayerArn} from './get-extension-layer-arn'
 import {DecisionAlgorithmType, OptimeistProps} from './types'
 
@@ -44,6 +45,11 @@ export class LambdaFunction extends cdk.aws_lambda.Function {
      */
     accessTokenSecret.grantRead(this)
 
+    /**
+     * Configure the optional features and grant the permissions they need
+     */
+    configureOptionalFeatures(this, props.optimeistProps)
+
     /**
      * Grant the Lambda function permission to update its own configuration.
      * Required for update from the lambda layer.
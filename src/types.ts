@@ -1,9 +1,30 @@
+import * as cdk from 'aws-cdk-lib'
+
 export enum DecisionAlgorithmType {
   COST = 'COST',
   SPEED = 'SPEED',
   BALANCED = 'BALANCED',
 }
 
+/**
+ * The BALANCED decision algorithm with a custom weight of the cost from 0 to 1,
+ * 1 is the same as COST and 0 is the same as SPEED
+ */
+export type WeightedDecisionAlgorithmType = `${DecisionAlgorithmType.BALANCED}#${number}`
+
+/**
+ * Creates the BALANCED decision algorithm with the given weight of the cost
+ *
+ * @param weight The share of the cost from 0 to 1, BALANCED uses 0.5
+ */
+export const weightedBalanced = (weight: number): WeightedDecisionAlgorithmType => {
+  if (!Number.isFinite(weight) || weight < 0 || weight > 1) {
+    throw new Error(`BALANCED weight must be a number from 0 to 1, got ${weight}`)
+  }
+
+  return `${DecisionAlgorithmType.BALANCED}#${weight}`
+}
+
 export type OptimeistProps = {
   /**
    * The name of the secret containing the access token
@@ -12,9 +33,70 @@ export type OptimeistProps = {
   accessTokenSecretName: string
 
   /**
-   * The decision algorithm to use
+   * The decision algorithm to use, use `weightedBalanced` for a custom BALANCED weight
    *
    * @default DecisionAlgorithmType.BALANCED
    */
-  decisionAlgorithmType?: DecisionAlgorithmType
+  decisionAlgorithmType?: DecisionAlgorithmType | WeightedDecisionAlgorithmType
+
+  /**
+   * The DynamoDB table to coordinate the memory changes of the concurrent execution environments,
+   * only one of them applies a change at a time. Required by the rollbacks and the canary rollouts,
+   * which keep their state in it.
+   * The table must have the `functionArn` string partition key.
+   *
+   * @default - no coordination
+   */
+  leaseTable?: cdk.aws_dynamodb.ITable
+
+  /**
+   * The alias to move to a new version published after every memory change,
+   * for the functions invoked through an alias instead of $LATEST
+   *
+   * @default - only $LATEST is updated
+   */
+  publishAlias?: string
+
+  /**
+   * The share of the publish alias traffic from 0 to 1 routed to the new memory size first,
+   * it's promoted or reverted once both versions can be compared.
+   * Requires the `publishAlias` and the `leaseTable`, which keeps the stats of the rollout.
+   *
+   * @default - the alias is moved to the new version at once
+   */
+  canaryWeight?: number
+
+  /**
+   * Where the extension records the memory changes, `SSM` keeps them in the history
+   * of the `/optimeist/<function name>/audit` parameter listed by `optimeist history`.
+   * SSM keeps the last 100 versions of a parameter, so only the last 100 changes are listed.
+   *
+   * @default 'LOG' - the function logs only
+   */
+  auditSink?: 'LOG' | 'SSM'
+
+  /**
+   * Where the memory size is decided, `LOCAL` computes it in the extension from the function
+   * metrics without calling the backend. The metrics are kept in the `leaseTable` if set,
+   * otherwise in the `/optimeist/<function name>/stats` parameter.
+   *
+   * @default 'REMOTE'
+   */
+  decisionMode?: 'REMOTE' | 'LOCAL'
+
+  /**
+   * Minimum time between two memory changes, each change causes a wave of cold starts.
+   * The applied changes are kept in the `/optimeist/<function name>/changes` parameter.
+   *
+   * @default - no cooldown
+   */
+  updateCooldown?: cdk.Duration
+
+  /**
+   * Maximum number of memory changes during the last day.
+   * The applied changes are kept in the `/optimeist/<function name>/changes` parameter.
+   *
+   * @default - no limit
+   */
+  maxChangesPerDay?: number
 }